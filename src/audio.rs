@@ -0,0 +1,307 @@
+//! Audio processing stages shared by the preview and the render
+use std::collections::VecDeque;
+use crate::effects::{Chain, Effect};
+use crate::rational::Rational;
+
+/// Gain of the bus the voices are summed into
+const MASTER_GAIN: f32 = 1.0;
+/// The limiter keeps the output peaks below this level (about -1 dBFS)
+const LIMITER_THRESHOLD: f32 = 0.89;
+/// How far ahead the limiter looks for peaks. Also the time it takes the gain
+/// to go down, and the latency of the whole stage.
+const LIMITER_LOOKAHEAD: f32 = 0.005;
+/// Time constant of the gain recovering after a peak
+const LIMITER_RELEASE: f32 = 0.150;
+
+/// Splits the audio into the frames (or fields) of the video at `rate`.
+///
+/// A frame rarely lasts a whole number of samples (44100 Hz at 60 fps,
+/// 48000 Hz at 30000/1001 fps), so the remainder is carried over to the next
+/// frame. The frames get slightly varying numbers of samples, but after `n`
+/// frames exactly `round(n * sample_rate / rate)` samples are handed out and
+/// the audio never drifts away from the video.
+pub struct SampleClock {
+    /// Samples per frame as a fraction
+    samples_per_frame: Rational,
+    remainder: usize,
+}
+
+impl SampleClock {
+    pub fn new(sample_rate: usize, rate: Rational) -> Self {
+        Self {
+            samples_per_frame: Rational::new(sample_rate*rate.den, rate.num),
+            // Half a sample to start with rounds the frame boundaries to the
+            // nearest sample
+            remainder: rate.num/2,
+        }
+    }
+
+    /// Number of samples (per channel) of the next frame
+    pub fn next_frame(&mut self) -> usize {
+        let total = self.samples_per_frame.num + self.remainder;
+        self.remainder = total%self.samples_per_frame.den;
+        total/self.samples_per_frame.den
+    }
+}
+
+/// Second order IIR filter in transposed direct form II
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// Coefficients of `b0 + b1 z^-1 + b2 z^-2` over `a0 + a1 z^-1 + a2 z^-2`
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b: b.map(|b| b/a[0]),
+            a: [a[1]/a[0], a[2]/a[0]],
+            z: [0.0; 2],
+        }
+    }
+
+    /// Second order low pass from the Audio EQ Cookbook
+    ///
+    /// https://www.w3.org/TR/audio-eq-cookbook/
+    pub fn low_pass(sample_rate: usize, freq: f64, q: f64) -> Self {
+        let w0 = 2.0*std::f64::consts::PI*freq/sample_rate as f64;
+        let alpha = w0.sin()/(2.0*q);
+        let cos = w0.cos();
+        Self::new(
+            [(1.0 - cos)/2.0, 1.0 - cos, (1.0 - cos)/2.0],
+            [1.0 + alpha, -2.0*cos, 1.0 - alpha],
+        )
+    }
+
+    /// Second order high pass from the Audio EQ Cookbook
+    pub fn high_pass(sample_rate: usize, freq: f64, q: f64) -> Self {
+        let w0 = 2.0*std::f64::consts::PI*freq/sample_rate as f64;
+        let alpha = w0.sin()/(2.0*q);
+        let cos = w0.cos();
+        Self::new(
+            [(1.0 + cos)/2.0, -(1.0 + cos), (1.0 + cos)/2.0],
+            [1.0 + alpha, -2.0*cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0]*x + self.z[0];
+        self.z[0] = self.b[1]*x - self.a[0]*y + self.z[1];
+        self.z[1] = self.b[2]*x - self.a[1]*y;
+        y
+    }
+}
+
+/// Lookahead peak limiter
+///
+/// The gain required by each frame is held for the lookahead window and then
+/// smoothed by a moving average of the same length. That way the gain reaches
+/// its minimum exactly when the peak leaves the delay line, so nothing above
+/// the threshold ever comes out. The channels share the gain, so the stereo
+/// image doesn't shift.
+pub struct Limiter {
+    channels: usize,
+    threshold: f32,
+    lookahead: usize,
+    release_coeff: f32,
+    /// Interleaved frames waiting to be output
+    delay: VecDeque<f32>,
+    /// Indices and required gains of the frames that may still be the minimum
+    /// of the hold window. The gains are increasing from front to back.
+    hold: VecDeque<(usize, f32)>,
+    released: f32,
+    /// The last `lookahead` released gains and their sum
+    average: VecDeque<f32>,
+    average_sum: f64,
+    frame_index: usize,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: usize, channels: usize, threshold: f32, lookahead: f32, release: f32) -> Self {
+        let lookahead = ((lookahead*sample_rate as f32).round() as usize).max(1);
+        Self {
+            channels,
+            threshold,
+            lookahead,
+            release_coeff: 1.0 - (-1.0/(release*sample_rate as f32)).exp(),
+            delay: VecDeque::from(vec![0.0; lookahead*channels]),
+            hold: VecDeque::new(),
+            released: 1.0,
+            average: VecDeque::from(vec![1.0; lookahead]),
+            average_sum: lookahead as f64,
+            frame_index: 0,
+        }
+    }
+
+    /// Limits the interleaved `samples` in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            let peak = frame.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            let required = if peak > self.threshold {self.threshold/peak} else {1.0};
+
+            // Minimum over the last lookahead + 1 frames
+            while self.hold.back().is_some_and(|(_, gain)| *gain >= required) {
+                self.hold.pop_back();
+            }
+            self.hold.push_back((self.frame_index, required));
+            while self.hold.front().is_some_and(|(index, _)| index + self.lookahead < self.frame_index) {
+                self.hold.pop_front();
+            }
+            let held = self.hold.front().map_or(1.0, |(_, gain)| *gain);
+
+            // Going down is handled by the moving average, going up is released slowly
+            if held < self.released {
+                self.released = held;
+            } else {
+                self.released += (held - self.released)*self.release_coeff;
+            }
+
+            self.average_sum += self.released as f64 - self.average.pop_front().unwrap_or(1.0) as f64;
+            self.average.push_back(self.released);
+            let gain = (self.average_sum/self.lookahead as f64) as f32;
+
+            for sample in frame.iter_mut() {
+                self.delay.push_back(*sample);
+                *sample = self.delay.pop_front().unwrap_or(0.0)*gain;
+            }
+            self.frame_index += 1;
+        }
+    }
+}
+
+/// The bus the voices are summed into: the effects, master gain and the limiter
+pub struct Mixer {
+    effects: Chain,
+    gain: f32,
+    limiter: Limiter,
+}
+
+impl Mixer {
+    /// `gain` is applied on top of the master gain, before the limiter
+    pub fn new(sample_rate: usize, channels: usize, gain: f32, effects: &[Effect]) -> Self {
+        Self {
+            effects: Chain::new(effects, sample_rate, channels),
+            gain: MASTER_GAIN*gain,
+            limiter: Limiter::new(sample_rate, channels, LIMITER_THRESHOLD, LIMITER_LOOKAHEAD, LIMITER_RELEASE),
+        }
+    }
+
+    /// Processes the interleaved sum of the voices in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        self.effects.process(samples);
+        for sample in samples.iter_mut() {
+            *sample *= self.gain;
+        }
+        self.limiter.process(samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    /// Samples handed out after each of the `frames` frames match the exact
+    /// boundary rounded to the nearest sample
+    fn assert_no_drift(sample_rate: usize, rate: Rational, frames: usize) {
+        let mut clock = SampleClock::new(sample_rate, rate);
+        let mut total = 0;
+        for n in 1..=frames {
+            total += clock.next_frame();
+            let exact = n as f64*sample_rate as f64*rate.den as f64/rate.num as f64;
+            assert_eq!(total, exact.round() as usize, "{sample_rate} Hz at {}/{} after {n} frames", rate.num, rate.den);
+        }
+    }
+
+    #[test]
+    fn sample_clock_does_not_drift() {
+        // An hour of video
+        for (num, den) in [(60, 1), (30000, 1001), (24000, 1001), (60000, 1001), (25, 1), (50, 1), (24, 1)] {
+            for sample_rate in [48000, 44100] {
+                assert_no_drift(sample_rate, Rational::new(num, den), 3600*num/den);
+            }
+        }
+    }
+
+    #[test]
+    fn sample_clock_splits_ntsc_frames() {
+        // 1601.6 samples per frame: 3 frames of 1602 and 2 of 1601 every 5 frames
+        let mut clock = SampleClock::new(48000, Rational::new(30000, 1001));
+        let frames: Vec<usize> = (0..10).map(|_| clock.next_frame()).collect();
+        assert_eq!(frames.iter().sum::<usize>(), 16016);
+        assert!(frames.iter().all(|samples| (1601..=1602).contains(samples)), "{frames:?}");
+    }
+
+    fn limiter() -> Limiter {
+        Limiter::new(SAMPLE_RATE, 2, LIMITER_THRESHOLD, LIMITER_LOOKAHEAD, LIMITER_RELEASE)
+    }
+
+    fn lookahead_frames() -> usize {
+        (LIMITER_LOOKAHEAD*SAMPLE_RATE as f32).round() as usize
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+    }
+
+    /// Room for the rounding of the running average
+    fn assert_below_threshold(samples: &[f32]) {
+        let peak = peak(samples);
+        assert!(peak <= LIMITER_THRESHOLD*(1.0 + 1e-5), "peak {peak} is above the threshold");
+    }
+
+    #[test]
+    fn limiter_delays_by_lookahead() {
+        let mut limiter = limiter();
+        let mut samples = vec![0.0; 2*1000];
+        samples[2*10] = 0.5;
+        samples[2*10 + 1] = -0.25;
+        limiter.process(&mut samples);
+        let delayed = 10 + lookahead_frames();
+        for (index, frame) in samples.chunks_exact(2).enumerate() {
+            let expected = if index == delayed {[0.5, -0.25]} else {[0.0, 0.0]};
+            assert_eq!(frame, expected, "frame {index}");
+        }
+    }
+
+    #[test]
+    fn limiter_catches_impulses() {
+        let mut limiter = limiter();
+        let mut samples = vec![0.0; 2*SAMPLE_RATE];
+        // Isolated ones, a burst closer than the lookahead and one per block
+        for (frame, level) in [(100, 4.0), (5000, 1.5), (5010, 8.0), (5020, 1.0), (20000, 100.0), (20001, -100.0)] {
+            samples[2*frame] = level;
+            samples[2*frame + 1] = -level/2.0;
+        }
+        let input = samples.clone();
+        for block in samples.chunks_mut(2*333) {
+            limiter.process(block);
+        }
+        assert_below_threshold(&samples);
+        // Everything is let through, only quieter
+        for frame in [100, 5000, 5010, 5020, 20000, 20001] {
+            let output = samples[2*(frame + lookahead_frames())];
+            assert!(output != 0.0 && output.signum() == input[2*frame].signum(), "impulse at {frame} is lost");
+        }
+    }
+
+    #[test]
+    fn limiter_holds_full_scale_square() {
+        let mut limiter = limiter();
+        let period = SAMPLE_RATE/100;
+        let mut samples: Vec<f32> = (0..SAMPLE_RATE)
+            .flat_map(|frame| {
+                let level = if frame%period < period/2 {1.0} else {-1.0};
+                [level, level]
+            })
+            .collect();
+        limiter.process(&mut samples);
+        assert_below_threshold(&samples);
+        // Once the gain settles the square is at the threshold
+        let settled = peak(&samples[samples.len()/2..]);
+        assert!(settled > LIMITER_THRESHOLD*0.999, "square settled at {settled}");
+    }
+}
@@ -8,77 +8,16 @@
 
 use std::fs;
 use std::result;
-use std::mem;
-use std::convert::*;
 use std::fmt;
 use std::io::{self, Write};
 use std::slice;
+use crate::rational::Rational;
+use crate::config::{Streams, VideoFormat, SOUND_CHANNELS};
+use crate::riff::*;
 
 type Result<T> = result::Result<T, ()>;
 
-fn chop_u32(content: &mut &[u8]) -> u32 {
-    let result = u32::from_le_bytes((*content)[0..4].try_into().unwrap());
-    (*content) = &(*content)[4..];
-    result
-}
-
-enum Entry<'a> {
-    Chunk(Chunk<'a>),
-    List(List<'a>),
-}
-
-fn chop_entry<'a>(content: &mut &'a [u8]) -> Entry<'a> {
-    let id = chop_u32(content);
-    if id != LIST {
-        let size = chop_u32(content) as usize;
-        let size_padded = pad_size(size, 2);
-        let chunk = Chunk {
-            id: FOURCC::from_u32(id),
-            content: &(*content)[0..size],
-        };
-        (*content) = &(*content)[size_padded..];
-        Entry::Chunk(chunk)
-    } else {
-        let size = chop_u32(content) as usize - size_of::<u32>();
-        let r#type = FOURCC::from_u32(chop_u32(content));
-        let list = List {
-            r#type,
-            content: &(*content)[0..size],
-        };
-        (*content) = &(*content)[size..];
-        Entry::List(list)
-    }
-}
-
-#[derive(Debug)]
-struct Chunk<'a> {
-    id: FOURCC,
-    content: &'a [u8]
-}
-
-fn chop_chunk<'a>(content: &mut &'a [u8]) -> Chunk<'a> {
-    match chop_entry(content) {
-        Entry::Chunk(chunk) => chunk,
-        _ => unreachable!("Not chunk"),
-    }
-}
-
-#[derive(Debug)]
-struct List<'a> {
-    r#type: FOURCC,
-    content: &'a [u8],
-}
-
-fn chop_list<'a>(content: &mut &'a [u8]) -> List<'a> {
-    match chop_entry(content) {
-        Entry::List(list) => list,
-        _ => unreachable!("Not list"),
-    }
-}
-
-const RIFF: u32 = 0x46464952;
 const AVI_: u32 = 0x20495641;
-const LIST: u32 = 0x5453494c;
 
 const hdrl: u32 = 0x6c726468;
 const avih: u32 = 0x68697661;   // AVI Main Header
@@ -88,47 +27,7 @@ const strl: u32 = 0x6c727473;
 const vids: u32 = 0x73646976;
 const auds: u32 = 0x73647561;
 const movi: u32 = 0x69766f6d;
-
-fn pad_size(size: usize, width: usize) -> usize {
-    (size + width - 1)/width*width
-}
-
-type WORD = u16;
-type DWORD = u32;
-type LONG = i32;
-type SHORT = i16;
-
-struct FOURCC([u8; 4]);
-
-impl FOURCC {
-    fn from_str(s: &str) -> Option<Self> {
-        s.as_bytes().try_into().ok().map(FOURCC)
-    }
-
-    const fn from_u32(x: u32) -> Self {
-        Self(x.to_le_bytes())
-    }
-
-    const fn to_u32(&self) -> u32 {
-        let Self(bytes) = self;
-        u32::from_le_bytes(*bytes)
-    }
-}
-
-impl fmt::Display for FOURCC {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> result::Result<(), fmt::Error> {
-        let FOURCC(bytes) = self;
-        write!(fmt, "0x{bytes:x} \"{str}\"",
-               bytes = u32::from_le_bytes(*bytes),
-               str = str::from_utf8(bytes).unwrap())
-    }
-}
-
-impl fmt::Debug for FOURCC {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> result::Result<(), fmt::Error> {
-        fmt::Display::fmt(self, fmt)
-    }
-}
+const vprp: u32 = 0x70727076;   // Video Properties Header
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -192,44 +91,6 @@ struct BITMAPINFOHEADER {
     biClrImportant: DWORD,
 }
 
-#[derive(Debug, Clone, Copy)]
-#[repr(C, packed(1))]
-struct WAVEFORMATEX {
-    wFormatTag: WORD,
-    nChannels: WORD,
-    nSamplesPerSec: DWORD,
-    nAvgBytesPerSec: DWORD,
-    nBlockAlign: WORD,
-    wBitsPerSample: WORD,
-    cbSize: WORD,
-}
-
-#[derive(Debug, Clone, Copy)]
-#[repr(C, packed(1))]
-struct GUID {
-    Data1: u32,
-    Data2: u16,
-    Data3: u16,
-    Data4: [u8; 8],
-}
-
-#[derive(Debug, Clone, Copy)]
-#[repr(C, packed(1))]
-struct WAVEFORMATEXTENSIBLE {
-    Format: WAVEFORMATEX,
-    Samples: WORD,//SamplesUnion,
-    dwChannelMask: DWORD,
-    SubFormat: GUID,
-}
-
-#[derive(Debug)]
-#[repr(C)]
-enum SamplesUnion {
-    wValidBitsPerSample(WORD),
-    wSamplesPerBlock(WORD),
-    wReserved(WORD),
-}
-
 struct Indent<T: fmt::Debug> {
     value: T,
     indent: usize,
@@ -258,26 +119,6 @@ impl fmt::Display for Padding {
     }
 }
 
-fn transmute_struct_to_chunk<'a, T>(id: FOURCC, s: &'a T) -> Chunk<'a> {
-    let content = unsafe {
-        slice::from_raw_parts(mem::transmute::<&'a T, *const u8>(s), size_of::<T>())
-    };
-    Chunk {id, content}
-}
-
-fn transmute_chunk_to_struct<'a, T>(chunk: &Chunk<'a>) -> &'a T {
-    let expected = size_of::<T>();
-    let actual = chunk.content.len() as usize;
-    assert!(
-        expected <= actual,
-        "Unexpected chunk size. Expected size of structure {}, but got {}",
-        expected, actual
-    );
-    unsafe {
-        mem::transmute::<*const u8, &'a T>(chunk.content.as_ptr())
-    }
-}
-
 fn parse_strl(mut content: &[u8], level: usize) {
     let chunk = chop_chunk(&mut content);
     assert!(chunk.id.to_u32() == strh);
@@ -420,23 +261,45 @@ fn hack_avi_file(file_path: &str) -> Result<()> {
     Ok(())
 }
 
-fn hardcoded_avi_main_header(dwTotalFrames: DWORD) -> AVIMainHeader {
+fn bits_per_pixel(container: &Container) -> usize {
+    if container.format.alpha {32} else {24}
+}
+
+fn frame_size(container: &Container) -> usize {
+    container.format.width*container.format.height*bits_per_pixel(container)/8
+}
+
+/// Index of the audio stream, it follows the video stream if there is one
+fn audio_stream_index(container: &Container) -> usize {
+    if container.streams.has_video() {1} else {0}
+}
+
+fn avi_main_header(container: &Container) -> AVIMainHeader {
+    let video_frame_size = if container.streams.has_video() {frame_size(container)} else {0};
+    let frame_size = video_frame_size + container.max_sound_len*size_of::<f32>();
+    // Without the video the length of the audio is counted in frames
+    let total_frames = if container.streams.has_video() {
+        container.frame_count
+    } else {
+        let fps = container.format.fps;
+        (container.sample_count as u128*fps.num as u128).div_ceil(container.sample_rate as u128*fps.den as u128) as usize
+    };
     AVIMainHeader {
-        dwMicroSecPerFrame: 16666,
-        dwMaxBytesPerSec: 86592000,
+        dwMicroSecPerFrame: container.format.fps.recip().mul_floor(1_000_000) as DWORD,
+        dwMaxBytesPerSec: container.format.fps.mul_floor(frame_size) as DWORD,
         dwPaddingGranularity: 0,
         dwFlags: 2320,
-        dwTotalFrames,
+        dwTotalFrames: total_frames as DWORD,
         dwInitialFrames: 0,
-        dwStreams: 2,
+        dwStreams: (container.streams.has_video() as usize + container.streams.has_audio() as usize) as DWORD,
         dwSuggestedBufferSize: 1048576,
-        dwWidth: 800,
-        dwHeight: 600,
+        dwWidth: container.format.width as DWORD,
+        dwHeight: container.format.height as DWORD,
         dwReserved: [0, 0, 0, 0],
     }
 }
 
-fn hardcoded_avi_stream_header_vids(dwLength: DWORD) -> AVIStreamHeader {
+fn avi_stream_header_vids(container: &Container) -> AVIStreamHeader {
     AVIStreamHeader {
         fccType: FOURCC::from_u32(vids),
         fccHandler: FOURCC::from_u32(0x0),
@@ -444,37 +307,108 @@ fn hardcoded_avi_stream_header_vids(dwLength: DWORD) -> AVIStreamHeader {
         wPriority: 0,
         wLanguage: 0,
         dwInitialFrames: 0,
-        dwScale: 1,
-        dwRate: 60,
+        dwScale: container.format.fps.den as DWORD,
+        dwRate: container.format.fps.num as DWORD,
         dwStart: 0,
-        dwLength,
-        dwSuggestedBufferSize: 1440000,
+        dwLength: container.frame_count as DWORD,
+        dwSuggestedBufferSize: (frame_size(container)) as DWORD,
         dwQuality: 4294967295,
         dwSampleSize: 0,
         rcFrame: RECT {
             left: 0,
             top: 0,
-            right: 800,
-            bottom: 600,
+            right: container.format.width as SHORT,
+            bottom: container.format.height as SHORT,
         },
     }
 }
 
-const HARDCODED_BITMAPINFOHEADER: BITMAPINFOHEADER = BITMAPINFOHEADER {
-    biSize: 40,
-    biWidth: 800,
-    biHeight: -600,
-    biPlanes: 1,
-    biBitCount: 24,
-    biCompression: 0,
-    biSizeImage: 1440000,
-    biXPelsPerMeter: 0,
-    biYPelsPerMeter: 0,
-    biClrUsed: 0,
-    biClrImportant: 0,
-};
-
-fn hardcoded_avi_stream_header_auds(dwLength: DWORD) -> AVIStreamHeader {
+fn bitmap_info_header(container: &Container) -> BITMAPINFOHEADER {
+    BITMAPINFOHEADER {
+        biSize: size_of::<BITMAPINFOHEADER>() as DWORD,
+        biWidth: container.format.width as LONG,
+        biHeight: -(container.format.height as LONG),
+        biPlanes: 1,
+        biBitCount: bits_per_pixel(container) as WORD,
+        biCompression: 0,
+        biSizeImage: (frame_size(container)) as DWORD,
+        biXPelsPerMeter: 0,
+        biYPelsPerMeter: 0,
+        biClrUsed: 0,
+        biClrImportant: 0,
+    }
+}
+
+/// Video Properties Header. Followed by `nbFieldPerFrame` of [`VIDEO_FIELD_DESC`].
+///
+/// http://www.jmcgowan.com/odmlff2.pdf
+#[derive(Debug)]
+#[repr(C)]
+struct VideoPropHeader {
+    VideoFormatToken: DWORD,
+    VideoStandard: DWORD,
+    dwVerticalRefreshRate: DWORD,
+    dwHTotalInT: DWORD,
+    dwVTotalInLines: DWORD,
+    dwFrameAspectRatio: DWORD,
+    dwFrameWidthInPixels: DWORD,
+    dwFrameHeightInLines: DWORD,
+    nbFieldPerFrame: DWORD,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct VIDEO_FIELD_DESC {
+    CompressedBMHeight: DWORD,
+    CompressedBMWidth: DWORD,
+    ValidBMHeight: DWORD,
+    ValidBMWidth: DWORD,
+    ValidBMXOffset: DWORD,
+    ValidBMYOffset: DWORD,
+    VideoXOffsetInT: DWORD,
+    VideoYValidStartLine: DWORD,
+}
+
+fn fabricate_vprp(container: &Container) -> Vec<u8> {
+    let (width, height) = (container.format.width as DWORD, container.format.height as DWORD);
+    let fields_per_frame = container.format.interlace.fields_per_frame();
+    // The frame aspect ratio is stored as two 16 bit halves: 0xXXXXYYYY
+    let frame_aspect = Rational::new(
+        container.format.width*container.format.pixel_aspect.num,
+        container.format.height*container.format.pixel_aspect.den,
+    ).reduce();
+    let header = VideoPropHeader {
+        VideoFormatToken: 0,
+        VideoStandard: 0,
+        // The display refreshes once per field
+        dwVerticalRefreshRate: (container.format.fps.to_f32()*fields_per_frame as f32).round() as DWORD,
+        dwHTotalInT: width,
+        dwVTotalInLines: height,
+        dwFrameAspectRatio: ((frame_aspect.num as DWORD) << 16) | frame_aspect.den as DWORD,
+        dwFrameWidthInPixels: width,
+        dwFrameHeightInLines: height,
+        nbFieldPerFrame: fields_per_frame as DWORD,
+    };
+    let mut result = Vec::new();
+    result.extend_from_slice(transmute_struct_to_chunk(FOURCC::from_u32(vprp), &header).content);
+    // The fields are woven into a single bitmap, listed in the temporal order
+    for field_index in 0..fields_per_frame {
+        let field = VIDEO_FIELD_DESC {
+            CompressedBMHeight: height/fields_per_frame as DWORD,
+            CompressedBMWidth: width,
+            ValidBMHeight: height/fields_per_frame as DWORD,
+            ValidBMWidth: width,
+            ValidBMXOffset: 0,
+            ValidBMYOffset: 0,
+            VideoXOffsetInT: 0,
+            VideoYValidStartLine: container.format.interlace.field_first_line(field_index) as DWORD,
+        };
+        result.extend_from_slice(transmute_struct_to_chunk(FOURCC::from_u32(vprp), &field).content);
+    }
+    result
+}
+
+fn avi_stream_header_auds(container: &Container) -> AVIStreamHeader {
     AVIStreamHeader {
         fccType: FOURCC::from_u32(auds),
         fccHandler: FOURCC::from_u32(0x1),
@@ -483,12 +417,12 @@ fn hardcoded_avi_stream_header_auds(dwLength: DWORD) -> AVIStreamHeader {
         wLanguage: 0,
         dwInitialFrames: 0,
         dwScale: 1,
-        dwRate: 48000,
+        dwRate: container.sample_rate as DWORD,
         dwStart: 0,
-        dwLength, // original 288000
-        dwSuggestedBufferSize: 3200, // original 4096
+        dwLength: container.sample_count as DWORD,
+        dwSuggestedBufferSize: (container.max_sound_len*size_of::<f32>()) as DWORD,
         dwQuality: 4294967295,
-        dwSampleSize: 4,
+        dwSampleSize: (SOUND_CHANNELS*size_of::<f32>()) as DWORD,
         rcFrame: RECT {
             left: 0,
             top: 0,
@@ -498,155 +432,145 @@ fn hardcoded_avi_stream_header_auds(dwLength: DWORD) -> AVIStreamHeader {
     }
 }
 
-const HARDCODED_WAVEFORMATEXTENSIBLE: WAVEFORMATEXTENSIBLE = WAVEFORMATEXTENSIBLE {
-    Format: WAVEFORMATEX {
-        wFormatTag: 65534,
-        nChannels: 1,
-        nSamplesPerSec: 48000,
-        nAvgBytesPerSec: 192000,
-        nBlockAlign: 4,
-        wBitsPerSample: 32,
-        cbSize: 22,
-    },
-    Samples: 32,
-    dwChannelMask: 4,
-    SubFormat: GUID {
-        Data1: 3,
-        Data2: 0,
-        Data3: 16,
-        Data4: [128, 0, 0, 170, 0, 56, 155, 113],
-    },
-};
-
-fn write_list<'a>(sink: &mut impl io::Write, list: &List<'a>) -> io::Result<()> {
-    sink.write(&LIST.to_le_bytes())?;
-    let list_size = (list.content.len() + size_of::<FOURCC>()) as u32;
-    sink.write(&list_size.to_le_bytes())?;
-    sink.write(&list.r#type.to_u32().to_le_bytes())?;
-    sink.write(list.content)?;
-    Ok(())
-}
-
-fn write_chunk<'a>(sink: &mut impl io::Write, chunk: &Chunk<'a>) -> io::Result<()> {
-    sink.write(&chunk.id.to_u32().to_le_bytes())?;
-    let chunk_size = chunk.content.len() as u32;
-    sink.write(&chunk_size.to_le_bytes())?;
-    sink.write(chunk.content)?;
-
-    if chunk_size%2 != 0 {
-        sink.write(&[0])?;
-    }
-
-    Ok(())
-}
 
-fn fabrivate_video_strl(dwLength: DWORD) -> io::Result<Vec<u8>> {
+fn fabrivate_video_strl(container: &Container) -> io::Result<Vec<u8>> {
     let mut result = Vec::new();
-    write_chunk(&mut result, &transmute_struct_to_chunk(FOURCC::from_u32(strh), &hardcoded_avi_stream_header_vids(dwLength)))?;
-    write_chunk(&mut result, &transmute_struct_to_chunk(FOURCC::from_u32(strf), &HARDCODED_BITMAPINFOHEADER))?;
+    write_chunk(&mut result, &transmute_struct_to_chunk(FOURCC::from_u32(strh), &avi_stream_header_vids(container)))?;
+    write_chunk(&mut result, &transmute_struct_to_chunk(FOURCC::from_u32(strf), &bitmap_info_header(container)))?;
+    write_chunk(&mut result, &Chunk {
+        id: FOURCC::from_u32(vprp),
+        content: &fabricate_vprp(container),
+    })?;
     Ok(result)
 }
 
-fn fabrivate_audio_strl(dwLength: DWORD) -> io::Result<Vec<u8>> {
+fn fabrivate_audio_strl(container: &Container) -> io::Result<Vec<u8>> {
     let mut result = Vec::new();
-    write_chunk(&mut result, &transmute_struct_to_chunk(FOURCC::from_u32(strh), &hardcoded_avi_stream_header_auds(dwLength)))?;
-    write_chunk(&mut result, &transmute_struct_to_chunk(FOURCC::from_u32(strf), &HARDCODED_WAVEFORMATEXTENSIBLE))?;
+    write_chunk(&mut result, &transmute_struct_to_chunk(FOURCC::from_u32(strh), &avi_stream_header_auds(container)))?;
+    write_chunk(&mut result, &transmute_struct_to_chunk(FOURCC::from_u32(strf), &float_wave_format(container.sample_rate, SOUND_CHANNELS)))?;
     Ok(result)
 }
 
-fn fabricate_hdrl(frames_count: usize) -> io::Result<Vec<u8>> {
+fn fabricate_hdrl(container: &Container) -> io::Result<Vec<u8>> {
     let mut result = Vec::new();
     write_chunk(
         &mut result,
         &transmute_struct_to_chunk(
             FOURCC::from_u32(avih),
-            &hardcoded_avi_main_header(frames_count as DWORD)
+            &avi_main_header(container)
         )
     )?;
-    write_list(&mut result, &List {
-        r#type: FOURCC::from_u32(strl),
-        content: &fabrivate_video_strl(frames_count as DWORD)?,
-    })?;
-    write_list(&mut result, &List {
-        r#type: FOURCC::from_u32(strl),
-        content: &fabrivate_audio_strl((frames_count*800) as DWORD)?,
-    })?;
+    if container.streams.has_video() {
+        write_list(&mut result, &List {
+            r#type: FOURCC::from_u32(strl),
+            content: &fabrivate_video_strl(container)?,
+        })?;
+    }
+    if container.streams.has_audio() {
+        write_list(&mut result, &List {
+            r#type: FOURCC::from_u32(strl),
+            content: &fabrivate_audio_strl(container)?,
+        })?;
+    }
     Ok(result)
 }
 
-fn fabricate_avi(movi_bytes: &[u8], frames_count: usize) -> io::Result<Vec<u8>> {
+fn fabricate_avi(container: &Container) -> io::Result<Vec<u8>> {
     let mut avi: Vec<u8> = Vec::new();
     avi.write(&AVI_.to_le_bytes())?;
     write_list(&mut avi, &List {
         r#type: FOURCC::from_u32(hdrl),
-        content: &fabricate_hdrl(frames_count)?,
+        content: &fabricate_hdrl(container)?,
     })?;
     write_list(&mut avi, &List {
         r#type: FOURCC::from_u32(movi),
-        content: movi_bytes,
+        content: &container.movi,
     })?;
     Ok(avi)
 }
 
-fn fabricate_avi_file(file_path: &str, movi_bytes: &[u8], frames_count: usize) -> io::Result<()> {
+fn fabricate_avi_file(file_path: &str, container: &Container) -> io::Result<()> {
     let mut riff: Vec<u8> = Vec::new();
 
     write_chunk(&mut riff, &Chunk {
         id: FOURCC::from_u32(RIFF),
-        content: &fabricate_avi(movi_bytes, frames_count)?,
+        content: &fabricate_avi(container)?,
     })?;
 
-    println!("Generating {file_path}...");
+    eprintln!("Generating {file_path}...");
     fs::write(file_path, &riff)
 }
 
+/// Top-down DIB pixels: BGR24, or BGRA32 if the alpha is kept
 #[derive(Default)]
-struct FrameBGR24 {
+struct FrameBGR {
     pixels: Vec<u8>,
 }
 
-impl FrameBGR24 {
-    fn from_canvas(&mut self, canvas: &[u32]) {
+impl FrameBGR {
+    fn from_canvas(&mut self, canvas: &[u32], alpha: bool) {
         self.pixels.clear();
         for pixel in canvas {
+            let a = ((pixel >> (8*3)) & 0xFF) as u8;
             let r = ((pixel >> (8*2)) & 0xFF) as u8;
             let b = ((pixel >> (8*0)) & 0xFF) as u8;
             let g = ((pixel >> (8*1)) & 0xFF) as u8;
             self.pixels.push(b);
             self.pixels.push(g);
             self.pixels.push(r);
+            if alpha {
+                self.pixels.push(a);
+            }
         }
     }
 }
 
 #[derive(Default)]
 pub struct Container {
-    frame_bgr24: FrameBGR24,
+    frame_bgr: FrameBGR,
     movi: Vec<u8>,
     frame_count: usize,
-    width: usize,
-    height: usize,
-    fps: usize,
+    /// Audio frames, i.e. samples of a single channel
+    sample_count: usize,
+    /// Longest audio chunk in samples of all the channels
+    max_sound_len: usize,
+    format: VideoFormat,
+    sample_rate: usize,
+    streams: Streams,
 }
 
 impl Container {
-    pub fn start(&mut self, width: usize, height: usize, fps: usize) {
+    /// The format of the video is kept even without the video stream, its
+    /// frame rate goes into the main header
+    pub fn start(&mut self, format: VideoFormat, sample_rate: usize, streams: Streams) {
         self.frame_count = 0;
+        self.sample_rate = sample_rate;
+        self.streams = streams;
+        self.sample_count = 0;
+        self.max_sound_len = 0;
         self.movi.clear();
-        self.width = width;
-        self.height = height;
-        self.fps = fps;
+        self.format = format;
     }
 
-    pub fn frame(&mut self, canvas: &[u32], sound: &[f32]) -> io::Result<()> {
+    /// Appends a video frame, its audio goes into a [`Container::sound`] chunk
+    pub fn video(&mut self, canvas: &[u32]) -> io::Result<()> {
+        assert!(self.streams.has_video(), "the container has no video stream");
         self.frame_count += 1;
-        self.frame_bgr24.from_canvas(canvas);
+        self.frame_bgr.from_canvas(canvas, self.format.alpha);
         write_chunk(&mut self.movi, &Chunk {
             id: FOURCC::from_str("00dc").unwrap(),
-            content: &self.frame_bgr24.pixels,
-        })?;
+            content: &self.frame_bgr.pixels,
+        })
+    }
+
+    /// Appends audio without a video frame, e.g. the tail of a resampler
+    pub fn sound(&mut self, sound: &[f32]) -> io::Result<()> {
+        assert!(self.streams.has_audio(), "the container has no audio stream");
+        self.sample_count += sound.len()/SOUND_CHANNELS;
+        self.max_sound_len = self.max_sound_len.max(sound.len());
+        let id = FOURCC::from_str(&format!("{:02}wb", audio_stream_index(self))).unwrap();
         write_chunk(&mut self.movi, &Chunk {
-            id: FOURCC::from_str("01wb").unwrap(),
+            id,
             content: unsafe {
                 slice::from_raw_parts(sound.as_ptr() as *const u8, sound.len()*size_of::<f32>())
             }
@@ -654,7 +578,7 @@ impl Container {
     }
 
     pub fn finish(&mut self, file_path: &str) -> io::Result<()> {
-        fabricate_avi_file(file_path, &self.movi, self.frame_count)
+        fabricate_avi_file(file_path, self)
     }
 }
 
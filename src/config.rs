@@ -1,5 +1,84 @@
+use crate::rational::Rational;
+
 pub const WIDTH: usize = 800;
 pub const HEIGHT: usize = 600;
-pub const FPS: usize = 60;
-pub const BACKGROUND: u32 = 0x181818;
+pub const FPS: Rational = Rational::new(60, 1);
+pub const PIXEL_ASPECT: Rational = Rational::new(1, 1);
+pub const BACKGROUND: u32 = 0xFF181818;
+pub const TRANSPARENT: u32 = 0x00000000;
 pub const SOUND_SAMPLE_RATE: usize = 48000;
+/// The sound is interleaved stereo everywhere
+pub const SOUND_CHANNELS: usize = 2;
+pub const INTERLACE: Interlace = Interlace::Progressive;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Interlace {
+    #[default]
+    Progressive,
+    /// Two fields per frame, the field with the even lines is the earlier one
+    TopFieldFirst,
+    /// Two fields per frame, the field with the odd lines is the earlier one
+    BottomFieldFirst,
+}
+
+impl Interlace {
+    pub fn fields_per_frame(self) -> usize {
+        match self {
+            Interlace::Progressive => 1,
+            Interlace::TopFieldFirst | Interlace::BottomFieldFirst => 2,
+        }
+    }
+
+    /// Index of the first line that belongs to the field `field_index` of a frame
+    pub fn field_first_line(self, field_index: usize) -> usize {
+        match self {
+            Interlace::Progressive => 0,
+            Interlace::TopFieldFirst => field_index,
+            Interlace::BottomFieldFirst => 1 - field_index,
+        }
+    }
+}
+
+impl std::str::FromStr for Interlace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "progressive" => Ok(Interlace::Progressive),
+            "top" => Ok(Interlace::TopFieldFirst),
+            "bottom" => Ok(Interlace::BottomFieldFirst),
+            _ => Err(format!("unknown interlace mode {s}, expected progressive, top or bottom")),
+        }
+    }
+}
+
+/// Which of the streams a render produces
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Streams {
+    #[default]
+    Both,
+    VideoOnly,
+    AudioOnly,
+}
+
+impl Streams {
+    pub fn has_video(self) -> bool {
+        self != Streams::AudioOnly
+    }
+
+    pub fn has_audio(self) -> bool {
+        self != Streams::VideoOnly
+    }
+}
+
+/// Everything the containers need to know about the video stream
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VideoFormat {
+    pub width: usize,
+    pub height: usize,
+    pub fps: Rational,
+    pub pixel_aspect: Rational,
+    pub interlace: Interlace,
+    /// Carry the alpha channel of the ARGB canvas into the output
+    pub alpha: bool,
+}
@@ -0,0 +1,411 @@
+//! Effects applied to the mix of the beeps before the limiter
+//!
+//! The effects of a scene are listed in the order they are applied, one
+//! `effect` line each. The parameters that are not given keep their defaults.
+//!
+//! ```text
+//! effect = highpass freq=80
+//! effect = delay time=0.375 feedback=0.35 mix=0.25
+//! effect = reverb room=0.8 damping=0.5 mix=0.3
+//! effect = dcblock
+//! ```
+use std::str::FromStr;
+use crate::audio::Biquad;
+
+/// Freeverb is tuned for 44.1 kHz, the lengths of the filters are scaled
+/// to the actual sample rate
+const FREEVERB_SAMPLE_RATE: f32 = 44100.0;
+const FREEVERB_COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const FREEVERB_ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+/// How much longer the filters of the right channel are
+const FREEVERB_STEREO_SPREAD: usize = 23;
+const FREEVERB_INPUT_GAIN: f32 = 0.015;
+const FREEVERB_ALLPASS_FEEDBACK: f32 = 0.5;
+/// Cutoff of the DC blocker, Hz
+const DC_BLOCKER_CUTOFF: f32 = 10.0;
+/// Highest cutoff of the low and high pass relative to the Nyquist frequency.
+/// The biquads go unstable at the Nyquist frequency and above.
+const FILTER_MAX_FREQ: f32 = 0.95;
+
+/// Settings of a single effect
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// Echoes every `time` seconds, each one `feedback` times quieter
+    Delay {time: f32, feedback: f32, mix: f32},
+    /// Freeverb. `room` is the size from 0.0 to 1.0, `damping` takes the high
+    /// frequencies out of the tail, `width` spreads it in the stereo image.
+    Reverb {room: f32, damping: f32, width: f32, mix: f32},
+    LowPass {freq: f32, q: f32},
+    HighPass {freq: f32, q: f32},
+    /// Removes the DC offset the effects or the samples may introduce
+    DcBlocker,
+}
+
+impl FromStr for Effect {
+    type Err = String;
+
+    /// Parses the name of the effect followed by `key=value` parameters
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().ok_or("no effect provided")?;
+        let mut effect = match name {
+            "delay" => Effect::Delay {time: 0.25, feedback: 0.4, mix: 0.3},
+            "reverb" => Effect::Reverb {room: 0.7, damping: 0.5, width: 1.0, mix: 0.25},
+            "lowpass" => Effect::LowPass {freq: 8000.0, q: std::f32::consts::FRAC_1_SQRT_2},
+            "highpass" => Effect::HighPass {freq: 40.0, q: std::f32::consts::FRAC_1_SQRT_2},
+            "dcblock" => Effect::DcBlocker,
+            _ => return Err(format!("unknown effect {name}, expected delay, reverb, lowpass, highpass or dcblock")),
+        };
+        for word in words {
+            let Some((key, value)) = word.split_once('=') else {
+                return Err(format!("expected `key=value` parameter of {name}, got `{word}`"));
+            };
+            let value: f32 = value.parse().map_err(|err| format!("invalid value of {key}: {err}"))?;
+            if !value.is_finite() {
+                return Err(format!("{key} of {name} must be finite"));
+            }
+            let param = match (&mut effect, key) {
+                (Effect::Delay {time, ..}, "time") => time,
+                (Effect::Delay {feedback, ..}, "feedback") => feedback,
+                (Effect::Delay {mix, ..}, "mix") => mix,
+                (Effect::Reverb {room, ..}, "room") => room,
+                (Effect::Reverb {damping, ..}, "damping") => damping,
+                (Effect::Reverb {width, ..}, "width") => width,
+                (Effect::Reverb {mix, ..}, "mix") => mix,
+                (Effect::LowPass {freq, ..} | Effect::HighPass {freq, ..}, "freq") => freq,
+                (Effect::LowPass {q, ..} | Effect::HighPass {q, ..}, "q") => q,
+                _ => return Err(format!("unknown parameter {key} of {name}")),
+            };
+            *param = value;
+        }
+        match effect {
+            Effect::Delay {time, feedback, ..} if time <= 0.0 || !(0.0..1.0).contains(&feedback) => {
+                Err("delay needs a positive time and a feedback below 1.0".to_string())
+            }
+            Effect::Reverb {room, damping, ..} if !(0.0..=1.0).contains(&room) || !(0.0..=1.0).contains(&damping) => {
+                Err("room and damping of reverb go from 0.0 to 1.0".to_string())
+            }
+            Effect::Delay {mix, ..} | Effect::Reverb {mix, ..} if !(0.0..=1.0).contains(&mix) => {
+                Err(format!("mix of {name} goes from 0.0 to 1.0"))
+            }
+            Effect::LowPass {freq, q} | Effect::HighPass {freq, q} if freq <= 0.0 || q <= 0.0 => {
+                Err(format!("{name} needs a positive freq and q"))
+            }
+            effect => Ok(effect),
+        }
+    }
+}
+
+/// Stereo feedback delay
+struct Delay {
+    /// Interleaved stereo frames of the last `time` seconds
+    line: Vec<f32>,
+    position: usize,
+    feedback: f32,
+    mix: f32,
+}
+
+impl Delay {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let delayed = self.line[self.position];
+            self.line[self.position] = *sample + delayed*self.feedback;
+            self.position = (self.position + 1)%self.line.len();
+            *sample += delayed*self.mix;
+        }
+    }
+}
+
+/// Feedback comb filter with a low pass in the loop
+struct Comb {
+    buffer: Vec<f32>,
+    position: usize,
+    filter_store: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Self {buffer: vec![0.0; len.max(1)], position: 0, filter_store: 0.0}
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.position];
+        self.filter_store = output*(1.0 - damping) + self.filter_store*damping;
+        self.buffer[self.position] = input + self.filter_store*feedback;
+        self.position = (self.position + 1)%self.buffer.len();
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self {buffer: vec![0.0; len.max(1)], position: 0}
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.position];
+        self.buffer[self.position] = input + buffered*FREEVERB_ALLPASS_FEEDBACK;
+        self.position = (self.position + 1)%self.buffer.len();
+        buffered - input
+    }
+}
+
+/// Schroeder reverb as tuned by Jezar in Freeverb: parallel damped combs
+/// followed by allpasses in series, for each of the channels
+///
+/// https://ccrma.stanford.edu/~jos/pasp/Freeverb.html
+struct Reverb {
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    feedback: f32,
+    damping: f32,
+    wet1: f32,
+    wet2: f32,
+}
+
+impl Reverb {
+    fn new(sample_rate: usize, room: f32, damping: f32, width: f32, mix: f32) -> Self {
+        let scale = |len: usize| (len as f32*sample_rate as f32/FREEVERB_SAMPLE_RATE).round() as usize;
+        let channel = |spread: usize| -> (Vec<Comb>, Vec<Allpass>) {
+            (
+                FREEVERB_COMBS.iter().map(|len| Comb::new(scale(len + spread))).collect(),
+                FREEVERB_ALLPASSES.iter().map(|len| Allpass::new(scale(len + spread))).collect(),
+            )
+        };
+        let (left_combs, left_allpasses) = channel(0);
+        let (right_combs, right_allpasses) = channel(FREEVERB_STEREO_SPREAD);
+        // The wet gain of 3.0 is the one of Freeverb
+        let wet = mix*3.0;
+        Self {
+            combs: [left_combs, right_combs],
+            allpasses: [left_allpasses, right_allpasses],
+            feedback: room*0.28 + 0.7,
+            damping: damping*0.4,
+            wet1: wet*(width/2.0 + 0.5),
+            wet2: wet*((1.0 - width)/2.0),
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(2) {
+            let input = (frame[0] + frame[1])*FREEVERB_INPUT_GAIN;
+            let mut outputs = [0.0; 2];
+            for (channel, output) in outputs.iter_mut().enumerate() {
+                *output = self.combs[channel].iter_mut()
+                    .map(|comb| comb.process(input, self.feedback, self.damping))
+                    .sum();
+                for allpass in self.allpasses[channel].iter_mut() {
+                    *output = allpass.process(*output);
+                }
+            }
+            frame[0] += outputs[0]*self.wet1 + outputs[1]*self.wet2;
+            frame[1] += outputs[1]*self.wet1 + outputs[0]*self.wet2;
+        }
+    }
+}
+
+/// One-pole high pass just above 0 Hz
+struct DcBlocker {
+    coeff: f32,
+    /// Previous input and output of each channel
+    state: Vec<(f32, f32)>,
+}
+
+impl DcBlocker {
+    fn process(&mut self, samples: &mut [f32]) {
+        let channels = self.state.len();
+        for (index, sample) in samples.iter_mut().enumerate() {
+            let (x1, y1) = &mut self.state[index%channels];
+            let y = *sample - *x1 + self.coeff**y1;
+            *x1 = *sample;
+            *y1 = y;
+            *sample = y;
+        }
+    }
+}
+
+enum Stage {
+    Delay(Delay),
+    Reverb(Reverb),
+    /// A filter per channel
+    Filter(Vec<Biquad>),
+    DcBlocker(DcBlocker),
+}
+
+/// The effects of a scene running over the interleaved stereo mix
+pub struct Chain {
+    stages: Vec<Stage>,
+}
+
+impl Chain {
+    /// The cutoffs of the filters are lowered below the Nyquist frequency of
+    /// the `sample_rate` if needed, the scene doesn't know it
+    pub fn new(effects: &[Effect], sample_rate: usize, channels: usize) -> Self {
+        assert_eq!(channels, 2, "the effects are stereo");
+        let max_freq = FILTER_MAX_FREQ as f64*sample_rate as f64/2.0;
+        let stages = effects.iter().map(|effect| match *effect {
+            Effect::Delay {time, feedback, mix} => Stage::Delay(Delay {
+                line: vec![0.0; ((time*sample_rate as f32).round() as usize).max(1)*channels],
+                position: 0,
+                feedback,
+                mix,
+            }),
+            Effect::Reverb {room, damping, width, mix} => Stage::Reverb(Reverb::new(sample_rate, room, damping, width, mix)),
+            Effect::LowPass {freq, q} => Stage::Filter(vec![Biquad::low_pass(sample_rate, (freq as f64).min(max_freq), q as f64); channels]),
+            Effect::HighPass {freq, q} => Stage::Filter(vec![Biquad::high_pass(sample_rate, (freq as f64).min(max_freq), q as f64); channels]),
+            Effect::DcBlocker => Stage::DcBlocker(DcBlocker {
+                coeff: (-2.0*std::f32::consts::PI*DC_BLOCKER_CUTOFF/sample_rate as f32).exp(),
+                state: vec![(0.0, 0.0); channels],
+            }),
+        }).collect();
+        Self {stages}
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for stage in self.stages.iter_mut() {
+            match stage {
+                Stage::Delay(delay) => delay.process(samples),
+                Stage::Reverb(reverb) => reverb.process(samples),
+                Stage::Filter(filters) => {
+                    let channels = filters.len();
+                    for (index, sample) in samples.iter_mut().enumerate() {
+                        *sample = filters[index%channels].process(*sample as f64) as f32;
+                    }
+                }
+                Stage::DcBlocker(dc_blocker) => dc_blocker.process(samples),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Effect, String> {
+        s.parse()
+    }
+
+    /// Runs a second of full-scale noise through the chain
+    fn noise_through(effects: &[Effect], sample_rate: usize) -> Vec<f32> {
+        let mut chain = Chain::new(effects, sample_rate, 2);
+        let mut state: u32 = 1;
+        let mut samples: Vec<f32> = (0..2*sample_rate).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32/u32::MAX as f32*2.0 - 1.0
+        }).collect();
+        chain.process(&mut samples);
+        samples
+    }
+
+    const SAMPLE_RATE: usize = 48000;
+
+    fn through(effect: &str, mut samples: Vec<f32>) -> Vec<f32> {
+        Chain::new(&[parse(effect).unwrap()], SAMPLE_RATE, 2).process(&mut samples);
+        samples
+    }
+
+    /// A second of stereo silence with a full-scale click on the left at the start
+    fn impulse() -> Vec<f32> {
+        let mut samples = vec![0.0; 2*SAMPLE_RATE];
+        samples[0] = 1.0;
+        samples
+    }
+
+    fn sine(freq: f32, offset: f32) -> Vec<f32> {
+        use std::f32::consts::PI;
+        (0..SAMPLE_RATE).flat_map(|n| {
+            let x = offset + (2.0*PI*freq*n as f32/SAMPLE_RATE as f32).sin();
+            [x, x]
+        }).collect()
+    }
+
+    /// RMS of the second half, after the filters settled
+    fn settled_rms(samples: &[f32]) -> f32 {
+        let tail = &samples[samples.len()/2..];
+        (tail.iter().map(|x| x*x).sum::<f32>()/tail.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn delay_echoes() {
+        // 480 frames apart
+        let output = through("delay time=0.01 feedback=0.5 mix=0.8", impulse());
+        for (index, sample) in output.iter().enumerate() {
+            let (frame, channel) = (index/2, index%2);
+            let expected = match frame {
+                0 if channel == 0 => 1.0,
+                _ if channel == 0 && frame%480 == 0 => 0.8*0.5f32.powi((frame/480 - 1) as i32),
+                _ => 0.0,
+            };
+            assert!((sample - expected).abs() < 1e-6, "frame {frame} channel {channel} is {sample} instead of {expected}");
+        }
+    }
+
+    #[test]
+    fn reverb_tail() {
+        let output = through("reverb room=0.8 mix=0.5", impulse());
+        assert_eq!(output, through("reverb room=0.8 mix=0.5", impulse()));
+        // The click stays dry, the tail comes after it in both channels
+        assert!((output[0] - 1.0).abs() < 1e-6);
+        let energy = |frames: std::ops::Range<usize>, channel: usize| -> f32 {
+            frames.map(|frame| output[2*frame + channel].powi(2)).sum()
+        };
+        for channel in 0..2 {
+            let early = energy(SAMPLE_RATE/20..SAMPLE_RATE/4, channel);
+            let late = energy(3*SAMPLE_RATE/4..SAMPLE_RATE, channel);
+            assert!(early > 1e-4, "no tail in channel {channel}");
+            assert!(late < early/10.0, "channel {channel} doesn't decay: {early} then {late}");
+        }
+        // Without the wet part nothing changes
+        assert_eq!(through("reverb mix=0", impulse()), impulse());
+    }
+
+    #[test]
+    fn filters_attenuate_the_stop_band() {
+        // 12 dB per octave, so at least 30 dB over 3 octaves
+        for (effect, pass, stop) in [("lowpass freq=1000", 100.0, 8000.0), ("highpass freq=1000", 8000.0, 125.0)] {
+            let pass = settled_rms(&through(effect, sine(pass, 0.0)));
+            let stop = settled_rms(&through(effect, sine(stop, 0.0)));
+            assert!((pass - 0.5f32.sqrt()).abs() < 0.01, "{effect} passes {pass}");
+            assert!(stop < 0.5f32.sqrt()*0.03, "{effect} lets {stop} through");
+        }
+    }
+
+    #[test]
+    fn dc_blocker_removes_the_offset() {
+        let output = through("dcblock", sine(1000.0, 0.5));
+        let tail = &output[output.len()/2..];
+        let mean = tail.iter().sum::<f32>()/tail.len() as f32;
+        assert!(mean.abs() < 1e-3, "offset {mean}");
+        assert!((settled_rms(&output) - 0.5f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn filters_above_nyquist_stay_stable() {
+        for effect in ["lowpass freq=30000", "highpass freq=30000 q=4", "lowpass freq=23999"] {
+            let output = noise_through(&[parse(effect).unwrap()], 48000);
+            assert!(output.iter().all(|sample| sample.is_finite() && sample.abs() < 100.0), "{effect} blows up");
+        }
+    }
+
+    #[test]
+    fn parameters_are_validated() {
+        for effect in [
+            "delay mix=1.5", "delay mix=-0.1", "reverb mix=2", "delay feedback=1", "delay time=0",
+            "reverb room=1.1", "lowpass freq=0", "highpass q=-1", "delay time=NaN", "lowpass freq=inf",
+            "delay speed=1", "chorus", "delay 0.5",
+        ] {
+            assert!(parse(effect).is_err(), "{effect} is accepted");
+        }
+        assert_eq!(parse("delay mix=1 time=0.5").unwrap(), Effect::Delay {time: 0.5, feedback: 0.4, mix: 1.0});
+        assert_eq!(parse("reverb mix=0").unwrap(), Effect::Reverb {room: 0.7, damping: 0.5, width: 1.0, mix: 0.0});
+        assert_eq!(parse("dcblock").unwrap(), Effect::DcBlocker);
+    }
+}
@@ -0,0 +1,169 @@
+//! Fast Fourier transform of real signals for looking at the sound
+//!
+//! https://en.wikipedia.org/wiki/Cooley%E2%80%93Tukey_FFT_algorithm
+use std::f32::consts::PI;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn norm(self) -> f32 {
+        self.re.hypot(self.im)
+    }
+}
+
+/// In place iterative radix-2 transform, the length must be a power of two
+pub fn fft(data: &mut [Complex]) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "the length of the FFT must be a power of two");
+
+    // Bit-reversal permutation
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0*PI/len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len/2 {
+                let (sin, cos) = (angle*k as f32).sin_cos();
+                let a = data[start + k];
+                let b = data[start + k + len/2];
+                let b = Complex {
+                    re: b.re*cos - b.im*sin,
+                    im: b.re*sin + b.im*cos,
+                };
+                data[start + k] = Complex {re: a.re + b.re, im: a.im + b.im};
+                data[start + k + len/2] = Complex {re: a.re - b.re, im: a.im - b.im};
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// Short-time spectrum analyzer with a Hann window
+pub struct Spectrum {
+    window: Vec<f32>,
+    buffer: Vec<Complex>,
+    /// Amplitudes of the bins from 0 Hz up to the Nyquist frequency
+    magnitudes: Vec<f32>,
+}
+
+impl Spectrum {
+    /// The `size` must be a power of two
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two(), "the size of the spectrum must be a power of two");
+        let window = (0..size).map(|n| 0.5 - 0.5*(2.0*PI*n as f32/size as f32).cos()).collect();
+        Self {
+            window,
+            buffer: vec![Complex::default(); size],
+            magnitudes: vec![0.0; size/2 + 1],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Analyzes the last `size` of the mono `samples`, padding the missing
+    /// ones with silence. A full scale sine comes out at about 1.0 in its bin.
+    pub fn analyze(&mut self, samples: &[f32]) -> &[f32] {
+        let size = self.size();
+        let samples = &samples[samples.len().saturating_sub(size)..];
+        let padding = size - samples.len();
+        for (index, value) in self.buffer.iter_mut().enumerate() {
+            let x = if index < padding {0.0} else {samples[index - padding]};
+            *value = Complex {re: x*self.window[index], im: 0.0};
+        }
+        fft(&mut self.buffer);
+        // The Hann window halves the amplitude and the other half of it is in
+        // the negative frequencies
+        let scale = 4.0/size as f32;
+        for (magnitude, value) in self.magnitudes.iter_mut().zip(self.buffer.iter()) {
+            *magnitude = value.norm()*scale;
+        }
+        &self.magnitudes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(size: usize, cycles: f32, amplitude: f32) -> Vec<f32> {
+        (0..size).map(|n| amplitude*(2.0*PI*cycles*n as f32/size as f32).sin()).collect()
+    }
+
+    fn peak_bin(magnitudes: &[f32]) -> usize {
+        (0..magnitudes.len()).max_by(|a, b| magnitudes[*a].total_cmp(&magnitudes[*b])).unwrap()
+    }
+
+    #[test]
+    fn impulse_is_flat() {
+        let mut data = vec![Complex::default(); 64];
+        data[0].re = 1.0;
+        fft(&mut data);
+        for value in data {
+            assert!((value.re - 1.0).abs() < 1e-6 && value.im.abs() < 1e-6, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn cosine_lands_in_its_bins() {
+        let n = 256;
+        let mut data: Vec<Complex> = (0..n)
+            .map(|i| Complex {re: (2.0*PI*5.0*i as f32/n as f32).cos(), im: 0.0})
+            .collect();
+        fft(&mut data);
+        for (k, value) in data.iter().enumerate() {
+            let expected = if k == 5 || k == n - 5 {n as f32/2.0} else {0.0};
+            assert!((value.norm() - expected).abs() < 1e-3, "bin {k} is {}", value.norm());
+        }
+    }
+
+    #[test]
+    fn sine_peaks_at_its_bin() {
+        for size in [256, 2048, 4096] {
+            let mut spectrum = Spectrum::new(size);
+            for (bin, amplitude) in [(1, 1.0), (10, 0.5), (size/4, 1.0), (size/2 - 3, 0.25)] {
+                let magnitudes = spectrum.analyze(&sine(size, bin as f32, amplitude));
+                assert_eq!(magnitudes.len(), size/2 + 1);
+                assert_eq!(peak_bin(magnitudes), bin);
+                assert!((magnitudes[bin] - amplitude).abs() < 1e-3*amplitude.max(1.0), "bin {bin} of {size} is {}", magnitudes[bin]);
+                // Hann leaks half into the neighbours and nothing further away
+                assert!((magnitudes[bin + 1] - amplitude/2.0).abs() < 1e-3);
+                assert!(magnitudes[bin + 3] < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn short_input_is_padded() {
+        let mut spectrum = Spectrum::new(1024);
+        assert!(spectrum.analyze(&[]).iter().all(|magnitude| *magnitude == 0.0));
+        // The last samples are analyzed
+        let mut samples = vec![1.0; 5000];
+        samples.extend(sine(1024, 64.0, 1.0));
+        assert_eq!(peak_bin(spectrum.analyze(&samples)), 64);
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn spectrum_size_must_be_power_of_two() {
+        Spectrum::new(1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn fft_length_must_be_power_of_two() {
+        fft(&mut vec![Complex::default(); 48]);
+    }
+}
@@ -0,0 +1,297 @@
+//! Loudness measurement according to EBU R128
+//!
+//! https://www.itu.int/rec/R-REC-BS.1770 (K-weighting, gating, true peak)
+//! https://tech.ebu.ch/docs/tech/tech3342.pdf (loudness range)
+use std::fmt;
+use crate::audio::Biquad;
+
+/// Loudness is measured over blocks made of these 100 ms sub-blocks
+const SUB_BLOCK_DURATION: f64 = 0.1;
+/// 400 ms momentary blocks of the integrated loudness
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+/// 3 s short-term blocks of the loudness range
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+const ABSOLUTE_GATE: f64 = -70.0;
+const INTEGRATED_RELATIVE_GATE: f64 = -10.0;
+const RANGE_RELATIVE_GATE: f64 = -20.0;
+const RANGE_LOW_PERCENTILE: f64 = 0.10;
+const RANGE_HIGH_PERCENTILE: f64 = 0.95;
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS_PER_PHASE: usize = 12;
+
+/// Loudness of the mean square `energy` of the K-weighted signal summed over the channels
+fn loudness_of(energy: f64) -> f64 {
+    -0.691 + 10.0*energy.log10()
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    if count == 0 {0.0} else {sum/count as f64}
+}
+
+/// The two stages of the K-weighting filter: a high shelf modelling the head
+/// followed by a high pass. The coefficients are derived for any sample rate
+/// the same way libebur128 does, at 48 kHz they match BS.1770 exactly.
+fn k_weighting(sample_rate: usize) -> [Biquad; 2] {
+    use std::f64::consts::PI;
+    let rate = sample_rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI*f0/rate).tan();
+    let vh = 10f64.powf(gain/20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let shelf = Biquad::new(
+        [vh + vb*k/q + k*k, 2.0*(k*k - vh), vh - vb*k/q + k*k],
+        [1.0 + k/q + k*k, 2.0*(k*k - 1.0), 1.0 - k/q + k*k],
+    );
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI*f0/rate).tan();
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [1.0 + k/q + k*k, 2.0*(k*k - 1.0), 1.0 - k/q + k*k],
+    );
+
+    [shelf, high_pass]
+}
+
+/// Finds the peaks between the samples by oversampling with a windowed-sinc
+/// interpolator
+struct TruePeak {
+    /// Polyphase coefficients, `TRUE_PEAK_TAPS_PER_PHASE` per phase
+    taps: Vec<f64>,
+    /// The last input samples, the most recent one first
+    history: [f64; TRUE_PEAK_TAPS_PER_PHASE],
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        use std::f64::consts::PI;
+        let len = TRUE_PEAK_OVERSAMPLING*TRUE_PEAK_TAPS_PER_PHASE;
+        let center = (len - 1) as f64/2.0;
+        let mut taps: Vec<f64> = (0..len).map(|n| {
+            let x = (n as f64 - center)/TRUE_PEAK_OVERSAMPLING as f64;
+            let sinc = if x == 0.0 {1.0} else {(PI*x).sin()/(PI*x)};
+            let hann = 0.5 - 0.5*(2.0*PI*(n as f64 + 0.5)/len as f64).cos();
+            sinc*hann
+        }).collect();
+        // Each phase passes DC with the unity gain
+        let sum: f64 = taps.iter().sum();
+        for tap in taps.iter_mut() {
+            *tap *= TRUE_PEAK_OVERSAMPLING as f64/sum;
+        }
+        Self {
+            taps,
+            history: [0.0; TRUE_PEAK_TAPS_PER_PHASE],
+            peak: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f64) {
+        self.history.copy_within(..TRUE_PEAK_TAPS_PER_PHASE - 1, 1);
+        self.history[0] = x;
+        for phase in 0..TRUE_PEAK_OVERSAMPLING {
+            let y: f64 = self.history.iter()
+                .enumerate()
+                .map(|(k, x)| self.taps[k*TRUE_PEAK_OVERSAMPLING + phase]*x)
+                .sum();
+            self.peak = self.peak.max(y.abs());
+        }
+    }
+}
+
+/// Result of the measurement
+#[derive(Debug, Clone, Copy)]
+pub struct Loudness {
+    /// Gated loudness of the whole program, LUFS
+    pub integrated: f64,
+    /// Spread of the short-term loudness, LU
+    pub range: f64,
+    /// dBTP
+    pub true_peak: f64,
+    /// dBFS
+    pub sample_peak: f64,
+}
+
+impl fmt::Display for Loudness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  Integrated:  {:.1} LUFS", self.integrated)?;
+        writeln!(f, "  Range:       {:.1} LU", self.range)?;
+        writeln!(f, "  True peak:   {:.1} dBTP", self.true_peak)?;
+        write!(f, "  Sample peak: {:.1} dBFS", self.sample_peak)
+    }
+}
+
+/// Accumulates interleaved samples and measures their loudness
+pub struct Meter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    true_peaks: Vec<TruePeak>,
+    sample_peak: f64,
+    sub_block_frames: usize,
+    /// Energy and frame count of the sub-block being accumulated
+    energy: f64,
+    frames: usize,
+    /// Mean square of each finished sub-block summed over the channels
+    sub_blocks: Vec<f64>,
+}
+
+impl Meter {
+    pub fn new(sample_rate: usize, channels: usize) -> Self {
+        Self {
+            channels,
+            filters: vec![k_weighting(sample_rate); channels],
+            true_peaks: (0..channels).map(|_| TruePeak::new()).collect(),
+            sample_peak: 0.0,
+            sub_block_frames: ((sample_rate as f64*SUB_BLOCK_DURATION).round() as usize).max(1),
+            energy: 0.0,
+            frames: 0,
+            sub_blocks: Vec::new(),
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let x = *sample as f64;
+                self.sample_peak = self.sample_peak.max(x.abs());
+                self.true_peaks[channel].process(x);
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let y = high_pass.process(shelf.process(x));
+                // Left and right have the weight of 1.0
+                self.energy += y*y;
+            }
+            self.frames += 1;
+            if self.frames == self.sub_block_frames {
+                self.sub_blocks.push(self.energy/self.frames as f64);
+                self.energy = 0.0;
+                self.frames = 0;
+            }
+        }
+    }
+
+    /// Mean energies of the blocks of `len` sub-blocks overlapping by all but one
+    fn blocks(&self, len: usize) -> Vec<f64> {
+        self.sub_blocks.windows(len).map(|window| window.iter().sum::<f64>()/len as f64).collect()
+    }
+
+    fn integrated(&self) -> f64 {
+        let blocks: Vec<f64> = self.blocks(MOMENTARY_SUB_BLOCKS)
+            .into_iter()
+            .filter(|energy| loudness_of(*energy) > ABSOLUTE_GATE)
+            .collect();
+        let relative_gate = loudness_of(mean(blocks.iter().copied())) + INTEGRATED_RELATIVE_GATE;
+        loudness_of(mean(blocks.into_iter().filter(|energy| loudness_of(*energy) > relative_gate)))
+    }
+
+    fn range(&self) -> f64 {
+        let blocks: Vec<f64> = self.blocks(SHORT_TERM_SUB_BLOCKS)
+            .into_iter()
+            .filter(|energy| loudness_of(*energy) > ABSOLUTE_GATE)
+            .collect();
+        let relative_gate = loudness_of(mean(blocks.iter().copied())) + RANGE_RELATIVE_GATE;
+        let mut loudness: Vec<f64> = blocks.into_iter()
+            .map(loudness_of)
+            .filter(|loudness| *loudness > relative_gate)
+            .collect();
+        if loudness.is_empty() {
+            return 0.0;
+        }
+        loudness.sort_by(f64::total_cmp);
+        let percentile = |p: f64| loudness[(p*(loudness.len() - 1) as f64).round() as usize];
+        percentile(RANGE_HIGH_PERCENTILE) - percentile(RANGE_LOW_PERCENTILE)
+    }
+
+    pub fn loudness(&self) -> Loudness {
+        let true_peak = self.true_peaks.iter().fold(self.sample_peak, |peak, true_peak| peak.max(true_peak.peak));
+        Loudness {
+            integrated: self.integrated(),
+            range: self.range(),
+            true_peak: 20.0*true_peak.log10(),
+            sample_peak: 20.0*self.sample_peak.log10(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Interleaved stereo sine with the same phase in both channels
+    fn stereo_sine(sample_rate: usize, freq: f64, dbfs: f64, seconds: f64) -> Vec<f32> {
+        use std::f64::consts::PI;
+        let amplitude = 10f64.powf(dbfs/20.0);
+        (0..(seconds*sample_rate as f64) as usize)
+            .flat_map(|i| {
+                let sample = (amplitude*(2.0*PI*freq*i as f64/sample_rate as f64).sin()) as f32;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn measure(sample_rate: usize, samples: &[f32]) -> Loudness {
+        let mut meter = Meter::new(sample_rate, 2);
+        for block in samples.chunks(2*1000) {
+            meter.process(block);
+        }
+        meter.loudness()
+    }
+
+    fn assert_near(actual: f64, expected: f64, tolerance: f64, what: &str) {
+        assert!((actual - expected).abs() <= tolerance, "{what} is {actual}, expected {expected}");
+    }
+
+    /// The reference signal of EBU Tech 3341: 1 kHz at -23 dBFS in both
+    /// channels reads -23 LUFS
+    #[test]
+    fn reference_sine() {
+        for sample_rate in [48000, 44100] {
+            let loudness = measure(sample_rate, &stereo_sine(sample_rate, 1000.0, -23.0, 20.0));
+            assert_near(loudness.integrated, -23.0, 0.1, "integrated loudness");
+            assert_near(loudness.range, 0.0, 0.1, "loudness range");
+            assert_near(loudness.sample_peak, -23.0, 0.01, "sample peak");
+            assert_near(loudness.true_peak, -23.0, 0.1, "true peak");
+        }
+    }
+
+    /// The loudness follows the level of the 1 kHz sine one to one
+    #[test]
+    fn loudness_follows_level() {
+        for dbfs in [-40.0, -20.0, -6.0] {
+            let loudness = measure(48000, &stereo_sine(48000, 1000.0, dbfs, 5.0));
+            assert_near(loudness.integrated, dbfs, 0.1, "integrated loudness");
+        }
+    }
+
+    /// K-weighting: the high shelf lifts 10 kHz by about 4 dB while 1 kHz
+    /// gets 0.7 dB of it, the high pass takes 20 Hz down by about 14 dB
+    #[test]
+    fn k_weighting_response() {
+        let at_1k = measure(48000, &stereo_sine(48000, 1000.0, -20.0, 5.0)).integrated;
+        let at_10k = measure(48000, &stereo_sine(48000, 10000.0, -20.0, 5.0)).integrated;
+        let at_20 = measure(48000, &stereo_sine(48000, 20.0, -20.0, 5.0)).integrated;
+        assert_near(at_10k - at_1k, 3.3, 0.1, "10 kHz relative to 1 kHz");
+        assert_near(at_20 - at_1k, -14.0, 1.5, "20 Hz relative to 1 kHz");
+    }
+
+    #[test]
+    fn silence_is_gated() {
+        let mut samples = stereo_sine(48000, 1000.0, -23.0, 10.0);
+        samples.extend(vec![0.0; 2*48000*10]);
+        // Only the few blocks over the end of the sine pass the gates, without
+        // them it would read 3 LU lower
+        assert_near(measure(48000, &samples).integrated, -23.0, 0.15, "integrated loudness");
+    }
+
+    #[test]
+    fn quiet_parts_are_gated_relative() {
+        // The -50 dBFS part is more than 10 LU below the rest
+        let mut samples = stereo_sine(48000, 1000.0, -23.0, 10.0);
+        samples.extend(stereo_sine(48000, 1000.0, -50.0, 10.0));
+        assert_near(measure(48000, &samples).integrated, -23.0, 0.15, "integrated loudness");
+    }
+}
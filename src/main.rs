@@ -4,36 +4,59 @@ mod preview;
 mod config;
 mod avi;
 mod yuv4mpeg2;
+mod rational;
+mod riff;
+mod wav;
+mod audio;
+mod synth;
+mod scene;
+mod music;
+mod midi;
+mod loudness;
+mod resample;
+mod effects;
+mod fft;
+mod plot;
+mod overlay;
+mod track;
+mod playback;
+mod mapping;
 
+use std::env;
 use std::io;
 use std::io::Write;
 
 struct Subcommand {
     name: &'static str,
     description: &'static str,
-    run: fn(program_name: &str),
+    run: fn(program_name: &str, args: env::Args),
 }
 
 const SUBCOMMANDS: &[Subcommand] = &[
     Subcommand {
         name: "render",
         description: "render the final video and audio files",
-        run: |_program_name| render::main().unwrap(),
+        run: |program_name, args| render::main(program_name, args).unwrap(),
     },
     Subcommand {
         name: "preview",
         description: "preview the video and audio",
-        run: |_program_name| preview::main(),
+        run: |program_name, args| preview::main(program_name, args).unwrap(),
     },
     Subcommand {
         name: "avi",
         description: "experiment with avi format",
-        run: |_program_name| avi::main().unwrap(),
+        run: |_program_name, _args| avi::main().unwrap(),
+    },
+    Subcommand {
+        name: "wav",
+        description: "print the format of a wav file",
+        run: |_program_name, mut args| wav::main(args.next()).unwrap(),
     },
     Subcommand {
         name: "help",
         description: "print this help message to stdout and exit with 0 code",
-        run: |program_name| print_help(&mut std::io::stdout(), program_name).unwrap(),
+        run: |program_name, _args| print_help(&mut std::io::stdout(), program_name).unwrap(),
     },
 ];
 
@@ -48,7 +71,7 @@ fn print_help(output: &mut impl Write, program_name: &str) -> io::Result<()> {
 }
 
 fn main() -> Result<(), ()> {
-    let mut args = std::env::args();
+    let mut args = env::args();
     let program_name = args.next().expect("Expected program name");
     let Some(subcommand_name) = args.next() else {
         print_help(&mut std::io::stderr(), &program_name).unwrap();
@@ -60,6 +83,6 @@ fn main() -> Result<(), ()> {
         eprintln!("ERROR: unknown subcommand: {}", subcommand_name);
         std::process::exit(1);
     };
-    (subcommand.run)(&program_name);
+    (subcommand.run)(&program_name, args);
     Ok(())
 }
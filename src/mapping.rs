@@ -0,0 +1,219 @@
+//! Parameters of the beeps taken from the rect that hit the wall
+//!
+//! Every `map` line of a scene sets one parameter of the beep from one
+//! property of the rect at the moment of the collision. The property is
+//! scaled from 0.0 to 1.0 and then mapped linearly from `min` to `max`, so
+//! `min` greater than `max` turns the mapping around. When the property is
+//! not given the parameter takes the one it is usually paired with. If the
+//! same parameter is mapped twice the last line wins.
+//!
+//! ```text
+//! # Big rects play low notes, small ones high
+//! map = pitch area min=12 max=-24
+//! # The color under the rect picks the instrument
+//! map = timbre hue
+//! map = volume size min=0.5 max=1
+//! map = duration size min=0.05 max=0.4
+//! ```
+use std::str::FromStr;
+
+/// Parameter of the beep set by a mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Semitones from A4, or degrees of the scale if the scene has one
+    Pitch,
+    /// Index of the instrument of the scene, the built-in ones go from the
+    /// soft sine to the noise, or of the sample if the scene has any
+    Timbre,
+    /// Gain relative to the usual volume of the beeps
+    Volume,
+    /// Seconds the note is held. The samples always play to the end.
+    Duration,
+}
+
+/// Property of the rect scaled from 0.0 to 1.0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// On a logarithmic scale from the smallest rect that still splits to
+    /// the first one, so every generation is an equal step
+    Area,
+    /// Side of the rect relative to the first one
+    Size,
+    /// Hue of the color drawn in the middle of the rect
+    Hue,
+    /// Which wall was hit, 0.0 for the sides and 1.0 for the top and the
+    /// bottom. The rects all move at the same speed along the diagonals, so
+    /// the speed towards the wall depends only on its orientation.
+    Velocity,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "area" => Ok(Source::Area),
+            "size" => Ok(Source::Size),
+            "hue" => Ok(Source::Hue),
+            "velocity" => Ok(Source::Velocity),
+            _ => Err(format!("unknown rect property {s}, expected area, size, hue or velocity")),
+        }
+    }
+}
+
+/// Properties of the rect at the collision, see [`Source`]
+#[derive(Debug, Clone, Copy)]
+pub struct Sources {
+    pub area: f32,
+    pub size: f32,
+    pub hue: f32,
+    pub velocity: f32,
+}
+
+impl Sources {
+    pub fn get(&self, source: Source) -> f32 {
+        match source {
+            Source::Area => self.area,
+            Source::Size => self.size,
+            Source::Hue => self.hue,
+            Source::Velocity => self.velocity,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+    pub target: Target,
+    pub source: Source,
+    /// Value of the parameter when the property is 0.0
+    pub min: f32,
+    /// Value of the parameter when the property is 1.0
+    pub max: f32,
+}
+
+impl Mapping {
+    /// The parameter of the beep for the rect with the `sources`
+    pub fn value(&self, sources: &Sources) -> f32 {
+        let t = sources.get(self.source).clamp(0.0, 1.0);
+        self.min + (self.max - self.min)*t
+    }
+}
+
+impl FromStr for Mapping {
+    type Err = String;
+
+    /// Parses the parameter, the optional property and `key=value` parameters
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace().peekable();
+        let name = words.next().ok_or("no parameter to map provided")?;
+        let mut mapping = match name {
+            "pitch" => Mapping {target: Target::Pitch, source: Source::Area, min: 12.0, max: -24.0},
+            "timbre" => Mapping {target: Target::Timbre, source: Source::Hue, min: 0.0, max: 4.0},
+            "volume" => Mapping {target: Target::Volume, source: Source::Size, min: 0.25, max: 1.0},
+            "duration" => Mapping {target: Target::Duration, source: Source::Size, min: 0.05, max: 0.4},
+            _ => return Err(format!("unknown parameter {name}, expected pitch, timbre, volume or duration")),
+        };
+        if let Some(source) = words.next_if(|word| !word.contains('=')) {
+            mapping.source = source.parse()?;
+        }
+        for word in words {
+            let Some((key, value)) = word.split_once('=') else {
+                return Err(format!("expected `key=value` parameter of {name}, got `{word}`"));
+            };
+            let param = match key {
+                "min" => &mut mapping.min,
+                "max" => &mut mapping.max,
+                _ => return Err(format!("unknown parameter {key} of {name}")),
+            };
+            *param = value.parse().map_err(|err| format!("invalid value of {key}: {err}"))?;
+            if !param.is_finite() {
+                return Err(format!("{key} of {name} must be finite"));
+            }
+        }
+        match mapping.target {
+            Target::Volume if mapping.min < 0.0 || mapping.max < 0.0 => {
+                Err("volume can't be negative".to_string())
+            }
+            Target::Duration if mapping.min <= 0.0 || mapping.max <= 0.0 => {
+                Err("duration must be positive".to_string())
+            }
+            _ => Ok(mapping),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(value: f32) -> Sources {
+        Sources {area: value, size: value, hue: value, velocity: value}
+    }
+
+    #[test]
+    fn defaults() {
+        let cases = [
+            ("pitch", Target::Pitch, Source::Area, 12.0, -24.0),
+            ("timbre", Target::Timbre, Source::Hue, 0.0, 4.0),
+            ("volume", Target::Volume, Source::Size, 0.25, 1.0),
+            ("duration", Target::Duration, Source::Size, 0.05, 0.4),
+        ];
+        for (s, target, source, min, max) in cases {
+            assert_eq!(s.parse(), Ok(Mapping {target, source, min, max}), "{s}");
+        }
+    }
+
+    #[test]
+    fn overrides() {
+        assert_eq!("pitch hue".parse(), Ok(Mapping {target: Target::Pitch, source: Source::Hue, min: 12.0, max: -24.0}));
+        assert_eq!(
+            "volume velocity min=0.5 max=2".parse(),
+            Ok(Mapping {target: Target::Volume, source: Source::Velocity, min: 0.5, max: 2.0}),
+        );
+        // The property can be left out with the parameters
+        assert_eq!(
+            "timbre max=2".parse(),
+            Ok(Mapping {target: Target::Timbre, source: Source::Hue, min: 0.0, max: 2.0}),
+        );
+    }
+
+    #[test]
+    fn reversed_range() {
+        let mapping: Mapping = "duration velocity min=0.4 max=0.1".parse().unwrap();
+        for (t, expected) in [(0.0, 0.4), (0.5, 0.25), (1.0, 0.1)] {
+            let value = mapping.value(&sources(t));
+            assert!((value - expected).abs() < 1e-6, "{t} maps to {value}");
+        }
+    }
+
+    #[test]
+    fn value_clamps() {
+        let mapping: Mapping = "pitch area min=0 max=10".parse().unwrap();
+        assert_eq!(mapping.value(&sources(-1.0)), 0.0);
+        assert_eq!(mapping.value(&sources(2.0)), 10.0);
+        assert_eq!(mapping.value(&sources(0.3)), 3.0);
+    }
+
+    #[test]
+    fn rejects() {
+        for s in [
+            "",
+            "loudness",
+            "pitch speed",
+            "pitch area min",
+            "pitch area gain=1",
+            "pitch area min=x",
+            "pitch area max=inf",
+            "pitch area min=NaN",
+            "pitch area hue",
+            "volume min=-0.5",
+            "volume max=-1",
+            "duration min=0",
+            "duration velocity max=-0.1",
+        ] {
+            assert!(s.parse::<Mapping>().is_err(), "{s:?} was accepted");
+        }
+        // Silence is fine
+        assert!("volume min=0 max=0".parse::<Mapping>().is_ok());
+    }
+}
@@ -0,0 +1,176 @@
+//! Standard MIDI File writer
+//!
+//! https://www.midi.org/specifications/file-format-specifications/standard-midi-files
+use std::io::{self, Write};
+
+/// Ticks per quarter note
+const DIVISION: u16 = 960;
+/// Tempo of the file when the scene has no grid
+pub const DEFAULT_BPM: f32 = 120.0;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const META: u8 = 0xFF;
+const META_TRACK_NAME: u8 = 0x03;
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_TEMPO: u8 = 0x51;
+const META_TIME_SIGNATURE: u8 = 0x58;
+
+/// A key going down or up at the audio frame `frame`
+#[derive(Debug, Clone, Copy)]
+pub struct NoteEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub velocity: u8,
+    pub on: bool,
+}
+
+/// MIDI key of a note in semitones from A4
+pub fn key_of_note(note: i32) -> u8 {
+    (69 + note).clamp(0, 127) as u8
+}
+
+fn write_vlq(track: &mut Vec<u8>, value: u64) {
+    let mut bytes = [0u8; 10];
+    let mut n = 0;
+    let mut value = value;
+    loop {
+        bytes[n] = (value & 0x7F) as u8 | if n > 0 {0x80} else {0};
+        n += 1;
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+    }
+    track.extend(bytes[..n].iter().rev());
+}
+
+fn write_meta(track: &mut Vec<u8>, delta: u64, kind: u8, data: &[u8]) {
+    write_vlq(track, delta);
+    track.extend([META, kind]);
+    write_vlq(track, data.len() as u64);
+    track.extend(data);
+}
+
+fn write_track(sink: &mut impl Write, track: &[u8]) -> io::Result<()> {
+    sink.write_all(b"MTrk")?;
+    sink.write_all(&(track.len() as u32).to_be_bytes())?;
+    sink.write_all(track)
+}
+
+/// Writes a format 1 file with the tempo track followed by the track of
+/// the notes on channel 1. The frames of the events are converted to the
+/// nearest ticks at `bpm`.
+pub fn write(sink: &mut impl Write, events: &[NoteEvent], sample_rate: usize, bpm: f32) -> io::Result<()> {
+    let micros_per_quarter = (60_000_000.0/bpm as f64).round() as u32;
+    let ticks_per_frame = DIVISION as f64*bpm as f64/(60.0*sample_rate as f64);
+
+    sink.write_all(b"MThd")?;
+    sink.write_all(&6u32.to_be_bytes())?;
+    sink.write_all(&1u16.to_be_bytes())?;
+    sink.write_all(&2u16.to_be_bytes())?;
+    sink.write_all(&DIVISION.to_be_bytes())?;
+
+    let mut tempo = Vec::new();
+    write_meta(&mut tempo, 0, META_TRACK_NAME, b"Tempo");
+    write_meta(&mut tempo, 0, META_TEMPO, &micros_per_quarter.to_be_bytes()[1..]);
+    // 4/4, 24 clocks per metronome click, 8 32nd notes per quarter
+    write_meta(&mut tempo, 0, META_TIME_SIGNATURE, &[4, 2, 24, 8]);
+    write_meta(&mut tempo, 0, META_END_OF_TRACK, &[]);
+    write_track(sink, &tempo)?;
+
+    // Releases go first, so a key struck again at the same tick isn't cut off
+    let mut events = events.to_vec();
+    events.sort_by_key(|event| (event.frame, event.on));
+
+    let mut notes = Vec::new();
+    write_meta(&mut notes, 0, META_TRACK_NAME, b"Beeps");
+    let mut last_tick = 0;
+    for event in &events {
+        let tick = (event.frame as f64*ticks_per_frame).round() as u64;
+        write_vlq(&mut notes, tick - last_tick);
+        last_tick = tick;
+        let status = if event.on {NOTE_ON} else {NOTE_OFF};
+        notes.extend([status, event.key & 0x7F, event.velocity & 0x7F]);
+    }
+    write_meta(&mut notes, 0, META_END_OF_TRACK, &[]);
+    write_track(sink, &notes)?;
+
+    sink.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vlq(value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_vlq(&mut bytes, value);
+        bytes
+    }
+
+    /// Splits the file into the header and the content of the tracks
+    fn chunks(mut file: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut chunks = Vec::new();
+        while !file.is_empty() {
+            let len = u32::from_be_bytes(file[4..8].try_into().unwrap()) as usize;
+            chunks.push((&file[..4], &file[8..8 + len]));
+            file = &file[8 + len..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn vlq_encoding() {
+        assert_eq!(vlq(0), [0x00]);
+        assert_eq!(vlq(0x40), [0x40]);
+        assert_eq!(vlq(0x7F), [0x7F]);
+        assert_eq!(vlq(0x80), [0x81, 0x00]);
+        assert_eq!(vlq(0x2000), [0xC0, 0x00]);
+        assert_eq!(vlq(0x3FFF), [0xFF, 0x7F]);
+        assert_eq!(vlq(0x4000), [0x81, 0x80, 0x00]);
+        assert_eq!(vlq(0x0FFFFFFF), [0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn file_layout() {
+        // At 120 bpm and 960 ticks per quarter a tick is 25 frames of 48 kHz
+        let events = [
+            NoteEvent {frame: 2500, key: 60, velocity: 100, on: false},
+            NoteEvent {frame: 0, key: 60, velocity: 100, on: true},
+            NoteEvent {frame: 2500, key: 60, velocity: 90, on: true},
+        ];
+        let mut file = Vec::new();
+        write(&mut file, &events, 48000, 120.0).unwrap();
+
+        let chunks = chunks(&file);
+        assert_eq!(chunks.len(), 3);
+        let (header_id, header) = chunks[0];
+        assert_eq!(header_id, b"MThd");
+        assert_eq!(header, [0, 1, 0, 2, 0x03, 0xC0]);
+        for (id, track) in &chunks[1..] {
+            assert_eq!(*id, b"MTrk");
+            assert!(track.ends_with(&[0x00, META, META_END_OF_TRACK, 0x00]), "track doesn't end with end of track");
+        }
+
+        let (_, tempo) = chunks[1];
+        assert!(tempo.windows(6).any(|w| w == [META, META_TEMPO, 3, 0x07, 0xA1, 0x20]), "500000 us per quarter is missing");
+
+        let (_, notes) = chunks[2];
+        let name = [0x00, META, META_TRACK_NAME, 5, b'B', b'e', b'e', b'p', b's'];
+        assert_eq!(&notes[..name.len()], name);
+        // The release goes before the key is struck again 100 ticks later
+        assert_eq!(&notes[name.len()..notes.len() - 4], [
+            0x00, NOTE_ON, 60, 100,
+            0x64, NOTE_OFF, 60, 100,
+            0x00, NOTE_ON, 60, 90,
+        ]);
+    }
+
+    #[test]
+    fn keys_are_clamped() {
+        assert_eq!(key_of_note(0), 69);
+        assert_eq!(key_of_note(-100), 0);
+        assert_eq!(key_of_note(100), 127);
+    }
+}
@@ -0,0 +1,171 @@
+//! Musical side of the sonification: scales for the notes and a tempo grid
+//! for the beeps, so the renders can be cut to music
+use std::str::FromStr;
+
+/// Notes are counted in semitones from A4 (440 Hz) everywhere
+const NOTE_NAMES: [(&str, i32); 7] = [("C", -9), ("D", -7), ("E", -5), ("F", -4), ("G", -2), ("A", 0), ("B", 2)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scale {
+    Chromatic,
+    Major,
+    Minor,
+    HarmonicMinor,
+    PentatonicMajor,
+    PentatonicMinor,
+    Blues,
+    WholeTone,
+}
+
+impl Scale {
+    /// Semitones of the degrees within an octave, starting from the root
+    fn intervals(self) -> &'static [i32] {
+        match self {
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::PentatonicMajor => &[0, 2, 4, 7, 9],
+            Scale::PentatonicMinor => &[0, 3, 5, 7, 10],
+            Scale::Blues => &[0, 3, 5, 6, 7, 10],
+            Scale::WholeTone => &[0, 2, 4, 6, 8, 10],
+        }
+    }
+
+    /// Note of the `degree` of the scale starting at `root`. The degrees past
+    /// the end of the scale continue in the next octaves.
+    pub fn note(self, root: i32, degree: i32) -> i32 {
+        let intervals = self.intervals();
+        let len = intervals.len() as i32;
+        root + degree.div_euclid(len)*12 + intervals[degree.rem_euclid(len) as usize]
+    }
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chromatic" => Ok(Scale::Chromatic),
+            "major" => Ok(Scale::Major),
+            "minor" => Ok(Scale::Minor),
+            "harmonic-minor" => Ok(Scale::HarmonicMinor),
+            "pentatonic-major" => Ok(Scale::PentatonicMajor),
+            "pentatonic-minor" => Ok(Scale::PentatonicMinor),
+            "blues" => Ok(Scale::Blues),
+            "whole-tone" => Ok(Scale::WholeTone),
+            _ => Err(format!("unknown scale {s}, expected chromatic, major, minor, harmonic-minor, pentatonic-major, pentatonic-minor, blues or whole-tone")),
+        }
+    }
+}
+
+/// Parses a note name like `A4`, `C#3` or `Eb2`, or a plain number of
+/// semitones from A4
+pub fn parse_note(s: &str) -> Result<i32, String> {
+    if let Ok(note) = s.parse() {
+        return Ok(note);
+    }
+    let invalid = || format!("invalid note {s}, expected a name like C#3 or semitones from A4");
+    let (letter, rest) = s.split_at_checked(1).ok_or_else(invalid)?;
+    let (_, mut note) = NOTE_NAMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(letter)).ok_or_else(invalid)?;
+    let octave = if let Some(octave) = rest.strip_prefix('#') {
+        note += 1;
+        octave
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        note -= 1;
+        octave
+    } else {
+        rest
+    };
+    let octave: i32 = octave.parse().map_err(|_| invalid())?;
+    Ok(note + (octave - 4)*12)
+}
+
+/// Grid of the beats the beeps are snapped to
+#[derive(Debug, Clone, Copy)]
+pub struct Grid {
+    pub bpm: f32,
+    /// Subdivision of a beat, e.g. 4 for sixteenth notes
+    pub steps_per_beat: usize,
+    /// Share of a pair of steps taken by the first one. 0.5 is straight,
+    /// 2/3 is the triplet shuffle.
+    pub swing: f32,
+}
+
+impl Grid {
+    /// The first point of the grid at or after `time` (in seconds)
+    pub fn quantize(&self, time: f64) -> f64 {
+        let pair = 2.0*60.0/(self.bpm as f64*self.steps_per_beat as f64);
+        let start = (time/pair).floor()*pair;
+        [start, start + pair*self.swing as f64, start + pair]
+            .into_iter()
+            .find(|point| *point >= time)
+            .unwrap_or(start + pair)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degrees_of_the_scales() {
+        let major: Vec<i32> = (0..=7).map(|degree| Scale::Major.note(0, degree)).collect();
+        assert_eq!(major, [0, 2, 4, 5, 7, 9, 11, 12]);
+        // Past the octave and below the root the scale goes on
+        assert_eq!(Scale::Major.note(0, 8), 14);
+        assert_eq!(Scale::Major.note(0, 14), 24);
+        assert_eq!(Scale::Major.note(0, -1), -1);
+        assert_eq!(Scale::Major.note(0, -7), -12);
+        assert_eq!(Scale::Major.note(0, -8), -13);
+        assert_eq!(Scale::PentatonicMinor.note(-7, 5), 5);
+        assert_eq!(Scale::PentatonicMinor.note(-7, -1), -9);
+        for degree in -30..30 {
+            assert_eq!(Scale::Chromatic.note(3, degree), 3 + degree);
+        }
+    }
+
+    #[test]
+    fn note_names() {
+        let cases = [
+            ("A4", 0), ("a4", 0), ("C4", -9), ("B3", -10), ("C5", 3),
+            ("C#3", -20), ("Eb2", -30), ("Cb4", -10), ("B#3", -9),
+            ("A-1", -60), ("G10", 70),
+            ("0", 0), ("-12", -12), ("7", 7),
+        ];
+        for (s, expected) in cases {
+            assert_eq!(parse_note(s), Ok(expected), "{s}");
+        }
+    }
+
+    #[test]
+    fn invalid_notes() {
+        for s in ["", "H4", "C", "C#", "Cx4", "#4", "C##4", "Cbb4", "C 4", "4A", "C4.5"] {
+            assert!(parse_note(s).is_err(), "{s:?} was accepted");
+        }
+    }
+
+    fn assert_quantized(grid: &Grid, time: f64, expected: f64) {
+        let point = grid.quantize(time);
+        assert!((point - expected).abs() < 1e-6, "{time} snaps to {point} instead of {expected}");
+    }
+
+    #[test]
+    fn straight_grid() {
+        // Sixteenths at 120 BPM are 0.125s apart
+        let grid = Grid {bpm: 120.0, steps_per_beat: 4, swing: 0.5};
+        for (time, expected) in [(0.0, 0.0), (0.01, 0.125), (0.125, 0.125), (0.13, 0.25), (0.3, 0.375), (1.0, 1.0), (1.01, 1.125)] {
+            assert_quantized(&grid, time, expected);
+        }
+    }
+
+    #[test]
+    fn swung_grid() {
+        // The off-beats move later, the on-beats stay
+        let grid = Grid {bpm: 120.0, steps_per_beat: 4, swing: 2.0/3.0};
+        let off_beat = 0.25*2.0/3.0;
+        for (time, expected) in [(0.0, 0.0), (0.01, off_beat), (0.125, off_beat), (off_beat, off_beat), (0.17, 0.25), (0.26, 0.25 + off_beat), (0.45, 0.5)] {
+            assert_quantized(&grid, time, expected);
+        }
+    }
+}
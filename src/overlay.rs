@@ -0,0 +1,273 @@
+//! Layers drawn over the rects that show the sound of the frame
+//!
+//! The overlays of a scene are drawn in the order they are listed, one
+//! `overlay` line each. The name is followed by `key=value` parameters, the
+//! ones that are not given keep their defaults. Positions and sizes are in
+//! pixels of the canvas, colors are `RRGGBB`.
+//!
+//! ```text
+//! overlay = scope x=20 y=20 width=240 height=80 color=80C0FF
+//! overlay = spectrum x=540 y=500 width=240 height=80 color=FF8040 bars=32
+//! ```
+use std::str::FromStr;
+use crate::fft::Spectrum;
+
+const SPECTRUM_FFT_SIZE: usize = 2048;
+/// The bars go from this frequency up to the Nyquist frequency on a
+/// logarithmic scale
+const SPECTRUM_MIN_FREQ: f32 = 40.0;
+/// Level of the empty bar, dBFS
+const SPECTRUM_FLOOR: f32 = -72.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlayKind {
+    /// Waveform of the mono downmix of the frame
+    Scope,
+    /// Levels of the frequency bands of the last samples
+    Spectrum,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overlay {
+    pub kind: OverlayKind,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// RGB, the overlays are opaque
+    pub color: u32,
+    /// Number of bars of the spectrum
+    pub bars: usize,
+}
+
+impl FromStr for Overlay {
+    type Err = String;
+
+    /// Parses the name of the overlay followed by `key=value` parameters
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().ok_or("no overlay provided")?;
+        let mut overlay = match name {
+            "scope" => Overlay {kind: OverlayKind::Scope, x: 20, y: 20, width: 240, height: 80, color: 0xFFFFFF, bars: 0},
+            "spectrum" => Overlay {kind: OverlayKind::Spectrum, x: 20, y: 500, width: 240, height: 80, color: 0xFFFFFF, bars: 32},
+            _ => return Err(format!("unknown overlay {name}, expected scope or spectrum")),
+        };
+        for word in words {
+            let Some((key, value)) = word.split_once('=') else {
+                return Err(format!("expected `key=value` parameter of {name}, got `{word}`"));
+            };
+            let param = match key {
+                "x" => &mut overlay.x,
+                "y" => &mut overlay.y,
+                "width" => &mut overlay.width,
+                "height" => &mut overlay.height,
+                "bars" if overlay.kind == OverlayKind::Spectrum => &mut overlay.bars,
+                "color" => {
+                    let hex = value.strip_prefix('#').unwrap_or(value);
+                    overlay.color = u32::from_str_radix(hex, 16).ok()
+                        .filter(|_| hex.len() == 6)
+                        .ok_or_else(|| format!("invalid color {value}, expected RRGGBB"))?;
+                    continue;
+                }
+                _ => return Err(format!("unknown parameter {key} of {name}")),
+            };
+            *param = value.parse().map_err(|err| format!("invalid value of {key}: {err}"))?;
+        }
+        if overlay.width == 0 || overlay.height == 0 {
+            return Err(format!("{name} needs a positive width and height"));
+        }
+        if overlay.kind == OverlayKind::Spectrum && overlay.bars == 0 {
+            return Err("spectrum needs at least one bar".to_string());
+        }
+        Ok(overlay)
+    }
+}
+
+/// Draws the overlays of a scene frame after frame
+pub struct Layers {
+    overlays: Vec<Overlay>,
+    sample_rate: usize,
+    spectrum: Spectrum,
+    /// The last mono samples, no more than the size of the FFT
+    history: Vec<f32>,
+    mono: Vec<f32>,
+}
+
+impl Layers {
+    pub fn new(overlays: &[Overlay], sample_rate: usize) -> Self {
+        Self {
+            overlays: overlays.to_vec(),
+            sample_rate,
+            spectrum: Spectrum::new(SPECTRUM_FFT_SIZE),
+            history: Vec::with_capacity(2*SPECTRUM_FFT_SIZE),
+            mono: Vec::new(),
+        }
+    }
+
+    /// Draws the overlays of the interleaved `sound` of the frame into the ARGB `canvas`
+    pub fn draw(&mut self, canvas: &mut [u32], canvas_stride: usize, sound: &[f32], channels: usize) {
+        if self.overlays.is_empty() {
+            return;
+        }
+        self.mono.clear();
+        self.mono.extend(sound.chunks_exact(channels).map(|frame| frame.iter().sum::<f32>()/channels as f32));
+        self.history.extend_from_slice(&self.mono);
+        let excess = self.history.len().saturating_sub(SPECTRUM_FFT_SIZE);
+        self.history.drain(..excess);
+
+        let mut magnitudes = None;
+        for overlay in &self.overlays {
+            let color = 0xFF << (8*3) | overlay.color;
+            let mut put = |x: usize, y: usize| {
+                let (x, y) = (overlay.x + x, overlay.y + y);
+                if x < canvas_stride && y < canvas.len()/canvas_stride {
+                    canvas[y*canvas_stride + x] = color;
+                }
+            };
+            match overlay.kind {
+                OverlayKind::Scope => {
+                    // Row of the sample value `v`, full scale at the edges
+                    let half = (overlay.height - 1) as f32/2.0;
+                    let row_of = |v: f32| (half - v.clamp(-1.0, 1.0)*half).round() as usize;
+                    let len = self.mono.len();
+                    let mut previous = None;
+                    for x in 0..overlay.width {
+                        // The samples under the column, joined to the previous
+                        // column so the line has no gaps
+                        let start = x*len/overlay.width;
+                        let end = ((x + 1)*len/overlay.width).max(start + 1).min(len);
+                        let (mut low, mut high) = previous.map_or((1.0f32, -1.0f32), |v| (v, v));
+                        for v in self.mono.get(start..end).unwrap_or(&[0.0]) {
+                            low = low.min(*v);
+                            high = high.max(*v);
+                        }
+                        previous = self.mono.get(end.saturating_sub(1)).copied();
+                        for y in row_of(high)..=row_of(low) {
+                            put(x, y);
+                        }
+                    }
+                }
+                OverlayKind::Spectrum => {
+                    let magnitudes = magnitudes.get_or_insert_with(|| self.spectrum.analyze(&self.history).to_vec());
+                    let bin_width = self.sample_rate as f32/SPECTRUM_FFT_SIZE as f32;
+                    let nyquist = self.sample_rate as f32/2.0;
+                    let freq_of_bar = |bar: usize| SPECTRUM_MIN_FREQ*(nyquist/SPECTRUM_MIN_FREQ).powf(bar as f32/overlay.bars as f32);
+                    for bar in 0..overlay.bars {
+                        let low = ((freq_of_bar(bar)/bin_width).round() as usize).min(magnitudes.len() - 1);
+                        let high = ((freq_of_bar(bar + 1)/bin_width).round() as usize).clamp(low + 1, magnitudes.len());
+                        let magnitude = magnitudes[low..high].iter().copied().fold(0.0, f32::max);
+                        let level = (1.0 - 20.0*magnitude.log10()/SPECTRUM_FLOOR).clamp(0.0, 1.0);
+                        let bar_height = (level*overlay.height as f32).round() as usize;
+                        // A pixel of gap between the bars when they are wide enough
+                        let left = bar*overlay.width/overlay.bars;
+                        let right = (bar + 1)*overlay.width/overlay.bars;
+                        let right = if right - left > 1 {right - 1} else {right};
+                        for x in left..right {
+                            for y in overlay.height - bar_height..overlay.height {
+                                put(x, y);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+    const WIDTH: usize = 400;
+    const HEIGHT: usize = 200;
+    const RED: u32 = 0xFFFF0000;
+
+    fn draw(overlay: &str, sound: &[f32]) -> Vec<u32> {
+        let mut layers = Layers::new(&[overlay.parse().unwrap()], SAMPLE_RATE);
+        let mut canvas = vec![0; WIDTH*HEIGHT];
+        layers.draw(&mut canvas, WIDTH, sound, 1);
+        canvas
+    }
+
+    fn painted_rows(canvas: &[u32], x: usize) -> Vec<usize> {
+        (0..HEIGHT).filter(|y| canvas[y*WIDTH + x] != 0).collect()
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!("scope".parse(), Ok(Overlay {kind: OverlayKind::Scope, x: 20, y: 20, width: 240, height: 80, color: 0xFFFFFF, bars: 0}));
+        assert_eq!("spectrum".parse(), Ok(Overlay {kind: OverlayKind::Spectrum, x: 20, y: 500, width: 240, height: 80, color: 0xFFFFFF, bars: 32}));
+    }
+
+    #[test]
+    fn overrides() {
+        assert_eq!(
+            "scope x=1 y=2 width=3 height=4 color=#102030".parse(),
+            Ok(Overlay {kind: OverlayKind::Scope, x: 1, y: 2, width: 3, height: 4, color: 0x102030, bars: 0}),
+        );
+        let overlay: Overlay = "spectrum color=80c0FF bars=8".parse().unwrap();
+        assert_eq!((overlay.color, overlay.bars), (0x80C0FF, 8));
+    }
+
+    #[test]
+    fn rejects() {
+        for s in [
+            "",
+            "circle",
+            "scope bars=8",
+            "scope width=0",
+            "spectrum height=0",
+            "spectrum bars=0",
+            "scope color=12345",
+            "scope color=#1234567",
+            "scope color=GGGGGG",
+            "scope x=-1",
+            "scope size=10",
+            "scope x",
+        ] {
+            assert!(s.parse::<Overlay>().is_err(), "{s:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn scope_of_silence() {
+        let canvas = draw("scope x=10 y=10 width=100 height=41 color=FF0000", &[0.0; 800]);
+        for x in 0..WIDTH {
+            let expected = if (10..110).contains(&x) {vec![30]} else {vec![]};
+            assert_eq!(painted_rows(&canvas, x), expected, "column {x}");
+        }
+        assert!(canvas.iter().all(|pixel| *pixel == 0 || *pixel == RED));
+    }
+
+    #[test]
+    fn clipped_at_the_edges() {
+        // Hangs over the right and the bottom edge
+        let canvas = draw("scope x=380 y=190 width=50 height=11 color=FF0000", &[0.0; 800]);
+        for x in 0..WIDTH {
+            let expected = if x >= 380 {vec![195]} else {vec![]};
+            assert_eq!(painted_rows(&canvas, x), expected, "column {x}");
+        }
+        // Entirely outside
+        let canvas = draw("spectrum x=1000 y=1000", &[1.0; 800]);
+        assert!(canvas.iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn sine_lights_its_bar() {
+        use std::f32::consts::PI;
+        // In the middle of a bin of the FFT, within the 17th of the 32 bars
+        // between 980 and 1200 Hz
+        let freq = 43.0*SAMPLE_RATE as f32/SPECTRUM_FFT_SIZE as f32;
+        let sound: Vec<f32> = (0..SPECTRUM_FFT_SIZE).map(|n| (2.0*PI*freq*n as f32/SAMPLE_RATE as f32).sin()).collect();
+        // 10 pixels per bar
+        let canvas = draw("spectrum x=0 y=0 width=320 height=100 bars=32", &sound);
+        assert_eq!(painted_rows(&canvas, 16*10 + 4), (0..100).collect::<Vec<_>>());
+        // The gap between the bars
+        assert!(painted_rows(&canvas, 16*10 + 9).is_empty());
+        for bar in (0..32).filter(|bar| *bar != 16) {
+            let rows = painted_rows(&canvas, bar*10 + 4);
+            assert!(rows.len() < 10, "bar {bar} is {} high", rows.len());
+        }
+    }
+}
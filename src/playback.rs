@@ -0,0 +1,571 @@
+//! Audio outputs of the preview
+//!
+//! The sound libraries are loaded at runtime instead of being linked, so the
+//! preview starts on the machines that don't have them and falls back to the
+//! other outputs.
+#![allow(non_camel_case_types)]
+
+use std::ffi::{c_void, CStr, CString};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong};
+use std::str::FromStr;
+use std::time::Instant;
+use crate::wav;
+
+/// Where the preview plays its sound. It is written from the audio thread
+/// of the preview.
+pub trait AudioOutput: Send {
+    fn name(&self) -> &'static str;
+    /// Plays the interleaved samples. Blocks while the buffer of the device is full.
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+    /// Seconds of the written sound that have not been heard yet. Zero means
+    /// the device has run out of sound.
+    fn latency(&mut self) -> io::Result<f64>;
+    /// Waits until everything written so far is played or saved
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// The first of PulseAudio, ALSA and the null output that opens
+    #[default]
+    Auto,
+    Pulse,
+    Alsa,
+    /// Saves the sound into a WAV file instead of playing it
+    Wav(String),
+    Null,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Backend::Auto),
+            "pulse" => Ok(Backend::Pulse),
+            "alsa" => Ok(Backend::Alsa),
+            "null" => Ok(Backend::Null),
+            _ => match s.strip_prefix("wav:") {
+                Some(path) if !path.is_empty() => Ok(Backend::Wav(path.to_string())),
+                _ => Err(format!("unknown audio output {s}, expected auto, pulse, alsa, null or wav:<path>")),
+            },
+        }
+    }
+}
+
+/// Opens the output of the `backend` for interleaved float samples.
+/// `program_name` is shown by the sound servers. The devices start playing
+/// once `start` seconds of sound are written, also after running out of it.
+/// By default they wait for their whole buffer, which the preview never fills.
+pub fn open(backend: &Backend, program_name: &str, sample_rate: usize, channels: usize, start: f64) -> io::Result<Box<dyn AudioOutput>> {
+    match backend {
+        Backend::Auto => {
+            let pulse = PulseAudio::open(program_name, sample_rate, channels, start);
+            let pulse_err = match pulse {
+                Ok(pulse) => return Ok(Box::new(pulse)),
+                Err(err) => err,
+            };
+            let alsa_err = match Alsa::open(sample_rate, channels, start) {
+                Ok(alsa) => return Ok(Box::new(alsa)),
+                Err(err) => err,
+            };
+            eprintln!("WARNING: {pulse_err}");
+            eprintln!("WARNING: {alsa_err}");
+            eprintln!("WARNING: no sound device is available, the preview is silent");
+            Ok(Box::new(Null {clock: WallClock::new(sample_rate, channels)}))
+        }
+        Backend::Pulse => Ok(Box::new(PulseAudio::open(program_name, sample_rate, channels, start)?)),
+        Backend::Alsa => Ok(Box::new(Alsa::open(sample_rate, channels, start)?)),
+        Backend::Wav(path) => Ok(Box::new(WavFile::create(path, sample_rate, channels)?)),
+        Backend::Null => Ok(Box::new(Null {clock: WallClock::new(sample_rate, channels)})),
+    }
+}
+
+/// How much sound the PulseAudio server buffers, seconds
+const PULSE_TARGET_LATENCY: f64 = 0.1;
+
+const RTLD_NOW: c_int = 2;
+
+extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *const c_char;
+}
+
+fn dl_error() -> String {
+    let message = unsafe { dlerror() };
+    if message.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned()
+    }
+}
+
+/// Shared library that stays loaded until the end of the program
+struct Library {
+    handle: *mut c_void,
+}
+
+impl Library {
+    /// Loads the first of the `names` that can be found
+    fn open(names: &[&str]) -> io::Result<Self> {
+        for name in names {
+            let name = CString::new(*name).unwrap();
+            let handle = unsafe { dlopen(name.as_ptr(), RTLD_NOW) };
+            if !handle.is_null() {
+                return Ok(Self {handle});
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("could not load {}: {}", names.join(" or "), dl_error())))
+    }
+
+    /// The function `name` of the library
+    ///
+    /// # Safety
+    ///
+    /// `F` must be the `extern "C" fn` type matching the declaration of the function
+    unsafe fn function<F: Copy>(&self, name: &str) -> io::Result<F> {
+        let symbol = CString::new(name).unwrap();
+        let address = dlsym(self.handle, symbol.as_ptr());
+        if address.is_null() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("could not find {name}: {}", dl_error())));
+        }
+        Ok(std::mem::transmute_copy(&address))
+    }
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+enum pa_stream_direction {
+    PA_STREAM_NODIRECTION,
+    PA_STREAM_PLAYBACK,
+    PA_STREAM_RECORD,
+    PA_STREAM_UPLOAD
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+enum pa_sample_format {
+    PA_SAMPLE_U8,
+    PA_SAMPLE_ALAW,
+    PA_SAMPLE_ULAW,
+    PA_SAMPLE_S16LE,
+    PA_SAMPLE_S16BE,
+    PA_SAMPLE_FLOAT32LE,
+    PA_SAMPLE_FLOAT32BE,
+    PA_SAMPLE_S32LE,
+    PA_SAMPLE_S32BE,
+    PA_SAMPLE_S24LE,
+    PA_SAMPLE_S24BE,
+    PA_SAMPLE_S24_32LE,
+    PA_SAMPLE_S24_32BE,
+    PA_SAMPLE_MAX,
+    PA_SAMPLE_INVALID = -1,
+}
+
+#[repr(C)]
+struct pa_sample_spec {
+    format: pa_sample_format,
+    rate: u32,
+    channels: u8,
+}
+
+/// `u32::MAX` leaves the value to the server
+#[repr(C)]
+struct pa_buffer_attr {
+    maxlength: u32,
+    tlength: u32,
+    prebuf: u32,
+    minreq: u32,
+    fragsize: u32,
+}
+
+type pa_usec_t = u64;
+
+type pa_simple = c_void;
+type pa_simple_new = unsafe extern "C" fn(server: *const c_char, name: *const c_char,
+                                          dir: pa_stream_direction,
+                                          dev: *const c_char,
+                                          stream_name: *const c_char,
+                                          ss: *const pa_sample_spec,
+                                          map: *const c_void,
+                                          attr: *const pa_buffer_attr,
+                                          error: *mut c_int) -> *mut pa_simple;
+type pa_simple_write = unsafe extern "C" fn(s: *mut pa_simple, data: *const c_void, bytes: usize, error: *mut c_int) -> c_int;
+type pa_simple_get_latency = unsafe extern "C" fn(s: *mut pa_simple, error: *mut c_int) -> pa_usec_t;
+type pa_simple_drain = unsafe extern "C" fn(s: *mut pa_simple, error: *mut c_int) -> c_int;
+type pa_simple_free = unsafe extern "C" fn(s: *mut pa_simple);
+type pa_strerror = unsafe extern "C" fn(error: c_int) -> *const c_char;
+
+/// Playback stream of the PulseAudio Simple API
+///
+/// https://freedesktop.org/software/pulseaudio/doxygen/simple.html
+struct PulseAudio {
+    stream: *mut pa_simple,
+    write: pa_simple_write,
+    get_latency: pa_simple_get_latency,
+    drain: pa_simple_drain,
+    free: pa_simple_free,
+    strerror: pa_strerror,
+}
+
+// SAFETY: the stream is used by one thread at a time, the Simple API keeps
+// no thread local state
+unsafe impl Send for PulseAudio {}
+
+impl PulseAudio {
+    fn open(program_name: &str, sample_rate: usize, channels: usize, start: f64) -> io::Result<Self> {
+        let simple = Library::open(&["libpulse-simple.so.0", "libpulse-simple.so"])?;
+        let pulse = Library::open(&["libpulse.so.0", "libpulse.so"])?;
+        unsafe {
+            let new: pa_simple_new = simple.function("pa_simple_new")?;
+            let strerror: pa_strerror = pulse.function("pa_strerror")?;
+
+            let program = CString::new(program_name).unwrap();
+            let stream_name = CString::new("playback").unwrap();
+            let ss = pa_sample_spec {
+                format: pa_sample_format::PA_SAMPLE_FLOAT32LE,
+                rate: sample_rate as u32,
+                channels: channels as u8,
+            };
+            // The default buffer of the server holds about two seconds, way
+            // too long for the picture to follow the sound
+            let bytes_per_second = (sample_rate*channels*size_of::<f32>()) as f64;
+            let attr = pa_buffer_attr {
+                maxlength: u32::MAX,
+                tlength: (PULSE_TARGET_LATENCY*bytes_per_second) as u32,
+                // Defaults to the whole tlength
+                prebuf: (start.min(PULSE_TARGET_LATENCY)*bytes_per_second) as u32,
+                minreq: u32::MAX,
+                fragsize: u32::MAX,
+            };
+            let mut error: c_int = 0;
+            let stream = new(std::ptr::null(),
+                             program.as_ptr(),
+                             pa_stream_direction::PA_STREAM_PLAYBACK,
+                             std::ptr::null(),
+                             stream_name.as_ptr(),
+                             &ss,
+                             std::ptr::null(),
+                             &attr,
+                             &mut error);
+            if stream.is_null() {
+                return Err(pulse_error(strerror, "pa_simple_new", error));
+            }
+            Ok(Self {
+                stream,
+                write: simple.function("pa_simple_write")?,
+                get_latency: simple.function("pa_simple_get_latency")?,
+                drain: simple.function("pa_simple_drain")?,
+                free: simple.function("pa_simple_free")?,
+                strerror,
+            })
+        }
+    }
+}
+
+fn pulse_error(strerror: pa_strerror, function: &str, error: c_int) -> io::Error {
+    let message = unsafe { CStr::from_ptr(strerror(error)) }.to_string_lossy().into_owned();
+    io::Error::other(format!("PulseAudio: {function}() failed: {message}"))
+}
+
+impl AudioOutput for PulseAudio {
+    fn name(&self) -> &'static str {
+        "PulseAudio"
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut error: c_int = 0;
+        if unsafe { (self.write)(self.stream, samples.as_ptr() as *const c_void, size_of_val(samples), &mut error) } < 0 {
+            return Err(pulse_error(self.strerror, "pa_simple_write", error));
+        }
+        Ok(())
+    }
+
+    fn latency(&mut self) -> io::Result<f64> {
+        let mut error: c_int = 0;
+        let latency = unsafe { (self.get_latency)(self.stream, &mut error) };
+        if latency == pa_usec_t::MAX {
+            return Err(pulse_error(self.strerror, "pa_simple_get_latency", error));
+        }
+        Ok(latency as f64/1_000_000.0)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut error: c_int = 0;
+        if unsafe { (self.drain)(self.stream, &mut error) } < 0 {
+            return Err(pulse_error(self.strerror, "pa_simple_drain", error));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PulseAudio {
+    fn drop(&mut self) {
+        unsafe { (self.free)(self.stream) }
+    }
+}
+
+type snd_pcm_t = c_void;
+type snd_pcm_sframes_t = c_long;
+type snd_pcm_uframes_t = c_ulong;
+type snd_pcm_open = unsafe extern "C" fn(pcm: *mut *mut snd_pcm_t, name: *const c_char, stream: c_int, mode: c_int) -> c_int;
+type snd_pcm_set_params = unsafe extern "C" fn(pcm: *mut snd_pcm_t, format: c_int, access: c_int,
+                                               channels: c_uint, rate: c_uint,
+                                               soft_resample: c_int, latency: c_uint) -> c_int;
+type snd_pcm_sw_params_t = c_void;
+type snd_pcm_sw_params_sizeof = unsafe extern "C" fn() -> usize;
+type snd_pcm_sw_params_current = unsafe extern "C" fn(pcm: *mut snd_pcm_t, params: *mut snd_pcm_sw_params_t) -> c_int;
+type snd_pcm_sw_params_set_start_threshold = unsafe extern "C" fn(pcm: *mut snd_pcm_t, params: *mut snd_pcm_sw_params_t,
+                                                                  val: snd_pcm_uframes_t) -> c_int;
+type snd_pcm_sw_params = unsafe extern "C" fn(pcm: *mut snd_pcm_t, params: *mut snd_pcm_sw_params_t) -> c_int;
+type snd_pcm_writei = unsafe extern "C" fn(pcm: *mut snd_pcm_t, buffer: *const c_void, size: snd_pcm_uframes_t) -> snd_pcm_sframes_t;
+type snd_pcm_recover = unsafe extern "C" fn(pcm: *mut snd_pcm_t, err: c_int, silent: c_int) -> c_int;
+type snd_pcm_delay = unsafe extern "C" fn(pcm: *mut snd_pcm_t, delay: *mut snd_pcm_sframes_t) -> c_int;
+type snd_pcm_drain = unsafe extern "C" fn(pcm: *mut snd_pcm_t) -> c_int;
+type snd_pcm_close = unsafe extern "C" fn(pcm: *mut snd_pcm_t) -> c_int;
+type snd_strerror = unsafe extern "C" fn(errnum: c_int) -> *const c_char;
+
+const SND_PCM_STREAM_PLAYBACK: c_int = 0;
+const SND_PCM_FORMAT_FLOAT_LE: c_int = 14;
+const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+/// Buffer of the device, microseconds
+const ALSA_LATENCY: c_uint = 100_000;
+
+/// The `default` PCM of ALSA
+///
+/// https://www.alsa-project.org/alsa-doc/alsa-lib/pcm.html
+struct Alsa {
+    pcm: *mut snd_pcm_t,
+    sample_rate: usize,
+    channels: usize,
+    writei: snd_pcm_writei,
+    recover: snd_pcm_recover,
+    delay: snd_pcm_delay,
+    drain: snd_pcm_drain,
+    close: snd_pcm_close,
+    strerror: snd_strerror,
+}
+
+fn alsa_error(strerror: snd_strerror, function: &str, error: c_int) -> io::Error {
+    let message = unsafe { CStr::from_ptr(strerror(error)) }.to_string_lossy().into_owned();
+    io::Error::other(format!("ALSA: {function}() failed: {message}"))
+}
+
+// SAFETY: the PCM is used by one thread at a time
+unsafe impl Send for Alsa {}
+
+impl Alsa {
+    fn open(sample_rate: usize, channels: usize, start: f64) -> io::Result<Self> {
+        let asound = Library::open(&["libasound.so.2", "libasound.so"])?;
+        unsafe {
+            let open: snd_pcm_open = asound.function("snd_pcm_open")?;
+            let set_params: snd_pcm_set_params = asound.function("snd_pcm_set_params")?;
+            let close: snd_pcm_close = asound.function("snd_pcm_close")?;
+            let strerror: snd_strerror = asound.function("snd_strerror")?;
+
+            let device = CString::new("default").unwrap();
+            let mut pcm = std::ptr::null_mut();
+            let error = open(&mut pcm, device.as_ptr(), SND_PCM_STREAM_PLAYBACK, 0);
+            if error < 0 {
+                return Err(alsa_error(strerror, "snd_pcm_open", error));
+            }
+            let error = set_params(pcm, SND_PCM_FORMAT_FLOAT_LE, SND_PCM_ACCESS_RW_INTERLEAVED,
+                                   channels as c_uint, sample_rate as c_uint, 1, ALSA_LATENCY);
+            if error < 0 {
+                close(pcm);
+                return Err(alsa_error(strerror, "snd_pcm_set_params", error));
+            }
+            // snd_pcm_set_params() waits for the whole buffer before starting
+            if let Err(err) = set_start_threshold(&asound, pcm, strerror, (start*sample_rate as f64) as snd_pcm_uframes_t) {
+                close(pcm);
+                return Err(err);
+            }
+            Ok(Self {
+                pcm,
+                sample_rate,
+                channels,
+                writei: asound.function("snd_pcm_writei")?,
+                recover: asound.function("snd_pcm_recover")?,
+                delay: asound.function("snd_pcm_delay")?,
+                drain: asound.function("snd_pcm_drain")?,
+                close,
+                strerror,
+            })
+        }
+    }
+}
+
+/// Sets the frames written that start the playback
+unsafe fn set_start_threshold(asound: &Library, pcm: *mut snd_pcm_t, strerror: snd_strerror, frames: snd_pcm_uframes_t) -> io::Result<()> {
+    let sizeof: snd_pcm_sw_params_sizeof = asound.function("snd_pcm_sw_params_sizeof")?;
+    let current: snd_pcm_sw_params_current = asound.function("snd_pcm_sw_params_current")?;
+    let set_start_threshold: snd_pcm_sw_params_set_start_threshold = asound.function("snd_pcm_sw_params_set_start_threshold")?;
+    let sw_params: snd_pcm_sw_params = asound.function("snd_pcm_sw_params")?;
+
+    // The structure is opaque, only its size is known
+    let mut params = vec![0u64; sizeof().div_ceil(size_of::<u64>())];
+    let params = params.as_mut_ptr() as *mut snd_pcm_sw_params_t;
+    let error = current(pcm, params);
+    if error < 0 {
+        return Err(alsa_error(strerror, "snd_pcm_sw_params_current", error));
+    }
+    let error = set_start_threshold(pcm, params, frames.max(1));
+    if error < 0 {
+        return Err(alsa_error(strerror, "snd_pcm_sw_params_set_start_threshold", error));
+    }
+    let error = sw_params(pcm, params);
+    if error < 0 {
+        return Err(alsa_error(strerror, "snd_pcm_sw_params", error));
+    }
+    Ok(())
+}
+
+impl AudioOutput for Alsa {
+    fn name(&self) -> &'static str {
+        "ALSA"
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut samples = samples;
+        while !samples.is_empty() {
+            let frames = samples.len()/self.channels;
+            let written = unsafe { (self.writei)(self.pcm, samples.as_ptr() as *const c_void, frames as snd_pcm_uframes_t) };
+            if written < 0 {
+                // Underruns and suspends are recovered from, the rest is fatal
+                let error = unsafe { (self.recover)(self.pcm, written as c_int, 1) };
+                if error < 0 {
+                    return Err(alsa_error(self.strerror, "snd_pcm_writei", error));
+                }
+                continue;
+            }
+            samples = &samples[written as usize*self.channels..];
+        }
+        Ok(())
+    }
+
+    fn latency(&mut self) -> io::Result<f64> {
+        let mut delay: snd_pcm_sframes_t = 0;
+        let error = unsafe { (self.delay)(self.pcm, &mut delay) };
+        if error < 0 {
+            // The device has run out of sound, the next write recovers it
+            return Ok(0.0);
+        }
+        Ok(delay.max(0) as f64/self.sample_rate as f64)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let error = unsafe { (self.drain)(self.pcm) };
+        if error < 0 {
+            return Err(alsa_error(self.strerror, "snd_pcm_drain", error));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Alsa {
+    fn drop(&mut self) {
+        unsafe { (self.close)(self.pcm) };
+    }
+}
+
+/// Plays the sound nowhere at the speed of a device, so the outputs
+/// without one still pace the preview
+struct WallClock {
+    sample_rate: usize,
+    channels: usize,
+    /// When the first sample would have been heard
+    start: Option<Instant>,
+    /// Frames written so far
+    written: usize,
+}
+
+impl WallClock {
+    fn new(sample_rate: usize, channels: usize) -> Self {
+        Self {sample_rate, channels, start: None, written: 0}
+    }
+
+    fn write(&mut self, samples: &[f32]) {
+        self.start.get_or_insert_with(Instant::now);
+        self.written += samples.len()/self.channels;
+    }
+
+    fn latency(&mut self) -> f64 {
+        let Some(start) = self.start else { return 0.0 };
+        let written = self.written as f64/self.sample_rate as f64;
+        let elapsed = start.elapsed().as_secs_f64();
+        if elapsed > written {
+            // Ran out of sound, the playback resumes with the next write
+            self.start = None;
+            self.written = 0;
+            return 0.0;
+        }
+        written - elapsed
+    }
+}
+
+struct WavFile {
+    writer: Option<wav::Writer<BufWriter<File>>>,
+    clock: WallClock,
+}
+
+impl WavFile {
+    fn create(path: &str, sample_rate: usize, channels: usize) -> io::Result<Self> {
+        let file = File::create(path).map_err(|err| io::Error::new(err.kind(), format!("{path}: {err}")))?;
+        Ok(Self {
+            writer: Some(wav::Writer::new(BufWriter::new(file), sample_rate, channels)?),
+            clock: WallClock::new(sample_rate, channels),
+        })
+    }
+}
+
+impl AudioOutput for WavFile {
+    fn name(&self) -> &'static str {
+        "WAV file"
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.clock.write(samples);
+        match self.writer.as_mut() {
+            Some(writer) => writer.write(samples),
+            None => Err(io::Error::other("the WAV file is finished already")),
+        }
+    }
+
+    fn latency(&mut self) -> io::Result<f64> {
+        Ok(self.clock.latency())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(writer) => writer.finish().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Throws the sound away, e.g. on CI machines without a sound card
+struct Null {
+    clock: WallClock,
+}
+
+impl AudioOutput for Null {
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.clock.write(samples);
+        Ok(())
+    }
+
+    fn latency(&mut self) -> io::Result<f64> {
+        Ok(self.clock.latency())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
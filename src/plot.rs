@@ -0,0 +1,293 @@
+//! Images of the soundtrack for reviewing the mix
+//!
+//! Every column of the images is one frame of the video, so the frame
+//! numbers of the video editor can be read straight off them. The columns
+//! that start a new second of the video are a little lighter.
+//!
+//! The images are written as binary PPM (P6), which most image viewers and
+//! `ffmpeg -i spectrogram.ppm spectrogram.png` can open.
+use std::io::{self, Write};
+use crate::fft::Spectrum;
+use crate::rational::Rational;
+
+const SPECTROGRAM_FFT_SIZE: usize = 4096;
+const SPECTROGRAM_HEIGHT: usize = 256;
+/// The rows of the spectrogram go from this frequency up to the Nyquist
+/// frequency on a logarithmic scale
+const SPECTROGRAM_MIN_FREQ: f32 = 20.0;
+/// Level of the darkest color of the spectrogram, dBFS
+const SPECTROGRAM_FLOOR: f32 = -96.0;
+/// Colors of the spectrogram from the floor up to 0 dBFS
+const SPECTROGRAM_PALETTE: [u32; 5] = [0x000000, 0x280060, 0xA01870, 0xF07820, 0xFFF0C0];
+
+/// Height of a channel in the waveform
+const WAVEFORM_CHANNEL_HEIGHT: usize = 128;
+const WAVEFORM_BACKGROUND: u32 = 0x181818;
+const WAVEFORM_PEAK: u32 = 0x406080;
+const WAVEFORM_RMS: u32 = 0x80C0FF;
+/// The frames that reach full scale
+const WAVEFORM_CLIPPED: u32 = 0xFF3030;
+const WAVEFORM_AXIS: u32 = 0x505050;
+
+/// How much the columns of the seconds are blended towards white
+const SECOND_MARK: f32 = 0.125;
+
+fn write_ppm(sink: &mut impl Write, width: usize, height: usize, pixels: &[u32]) -> io::Result<()> {
+    write!(sink, "P6\n{width} {height}\n255\n")?;
+    for pixel in pixels {
+        sink.write_all(&pixel.to_be_bytes()[1..])?;
+    }
+    sink.flush()
+}
+
+fn lerp_color(a: u32, b: u32, t: f32) -> u32 {
+    (0..3).fold(0, |color, channel| {
+        let shift = 8*channel;
+        let a = ((a >> shift) & 0xFF) as f32;
+        let b = ((b >> shift) & 0xFF) as f32;
+        color | ((a + (b - a)*t).round() as u32) << shift
+    })
+}
+
+/// Lightens the columns of the frames that start a new second
+fn mark_seconds(pixels: &mut [u32], width: usize, fps: Rational) {
+    for frame in 1..width {
+        // `frame*den/num` seconds have passed at the start of the frame
+        if frame*fps.den/fps.num != (frame - 1)*fps.den/fps.num {
+            for pixel in pixels.iter_mut().skip(frame).step_by(width) {
+                *pixel = lerp_color(*pixel, 0xFFFFFF, SECOND_MARK);
+            }
+        }
+    }
+}
+
+/// Spectrum of the mono downmix at the end of every frame. The frequency
+/// goes up and the level is colored from black to white.
+pub struct Spectrogram {
+    sample_rate: usize,
+    spectrum: Spectrum,
+    /// The last mono samples, no more than the size of the FFT
+    history: Vec<f32>,
+    /// Levels of the rows of each frame in dBFS, the lowest frequency first
+    columns: Vec<[f32; SPECTROGRAM_HEIGHT]>,
+}
+
+impl Spectrogram {
+    pub fn new(sample_rate: usize) -> Self {
+        Self {
+            sample_rate,
+            spectrum: Spectrum::new(SPECTROGRAM_FFT_SIZE),
+            history: Vec::with_capacity(2*SPECTROGRAM_FFT_SIZE),
+            columns: Vec::new(),
+        }
+    }
+
+    /// Adds the interleaved samples of a frame as the next column
+    pub fn frame(&mut self, samples: &[f32], channels: usize) {
+        self.history.extend(samples.chunks_exact(channels).map(|frame| frame.iter().sum::<f32>()/channels as f32));
+        let excess = self.history.len().saturating_sub(SPECTROGRAM_FFT_SIZE);
+        self.history.drain(..excess);
+
+        let magnitudes = self.spectrum.analyze(&self.history);
+        let bin_width = self.sample_rate as f32/SPECTROGRAM_FFT_SIZE as f32;
+        let nyquist = self.sample_rate as f32/2.0;
+        let freq_of_row = |row: usize| SPECTROGRAM_MIN_FREQ*(nyquist/SPECTROGRAM_MIN_FREQ).powf(row as f32/SPECTROGRAM_HEIGHT as f32);
+        let mut column = [SPECTROGRAM_FLOOR; SPECTROGRAM_HEIGHT];
+        for (row, level) in column.iter_mut().enumerate() {
+            // The loudest bin within the row, or the nearest one where the
+            // rows are narrower than the bins
+            let low = (freq_of_row(row)/bin_width).round() as usize;
+            let high = ((freq_of_row(row + 1)/bin_width).round() as usize).max(low + 1).min(magnitudes.len());
+            let magnitude = magnitudes[low.min(magnitudes.len() - 1)..high].iter().copied().fold(0.0, f32::max);
+            *level = (20.0*magnitude.log10()).max(SPECTROGRAM_FLOOR);
+        }
+        self.columns.push(column);
+    }
+
+    pub fn write(&self, sink: &mut impl Write, fps: Rational) -> io::Result<()> {
+        let width = self.columns.len();
+        let mut pixels = vec![0; width*SPECTROGRAM_HEIGHT];
+        for (x, column) in self.columns.iter().enumerate() {
+            for (row, level) in column.iter().enumerate() {
+                let t = (1.0 - level/SPECTROGRAM_FLOOR).clamp(0.0, 1.0)*(SPECTROGRAM_PALETTE.len() - 1) as f32;
+                let index = (t as usize).min(SPECTROGRAM_PALETTE.len() - 2);
+                let color = lerp_color(SPECTROGRAM_PALETTE[index], SPECTROGRAM_PALETTE[index + 1], t - index as f32);
+                pixels[(SPECTROGRAM_HEIGHT - 1 - row)*width + x] = color;
+            }
+        }
+        mark_seconds(&mut pixels, width, fps);
+        write_ppm(sink, width, SPECTROGRAM_HEIGHT, &pixels)
+    }
+}
+
+/// Peak and RMS level of each frame
+#[derive(Clone, Copy)]
+struct Levels {
+    min: f32,
+    max: f32,
+    rms: f32,
+}
+
+/// Overview of the levels of every frame, one channel above the other. The
+/// frames that reach full scale are red.
+pub struct Waveform {
+    channels: usize,
+    /// The levels of the channels of each frame
+    columns: Vec<Vec<Levels>>,
+}
+
+impl Waveform {
+    pub fn new(channels: usize) -> Self {
+        Self {channels, columns: Vec::new()}
+    }
+
+    /// Adds the interleaved samples of a frame as the next column
+    pub fn frame(&mut self, samples: &[f32]) {
+        let column = (0..self.channels).map(|channel| {
+            let samples = samples.iter().skip(channel).step_by(self.channels);
+            let (min, max, energy, count) = samples.fold((0.0f32, 0.0f32, 0.0, 0), |(min, max, energy, count), x| {
+                (min.min(*x), max.max(*x), energy + x*x, count + 1)
+            });
+            Levels {min, max, rms: (energy/count.max(1) as f32).sqrt()}
+        }).collect();
+        self.columns.push(column);
+    }
+
+    pub fn write(&self, sink: &mut impl Write, fps: Rational) -> io::Result<()> {
+        let width = self.columns.len();
+        let height = self.channels*WAVEFORM_CHANNEL_HEIGHT;
+        let mut pixels = vec![WAVEFORM_BACKGROUND; width*height];
+        let half = WAVEFORM_CHANNEL_HEIGHT as f32/2.0;
+        // Row of the sample value `x` within a channel, full scale at the edges
+        let row_of = |x: f32| ((half - x*half).round() as isize).clamp(0, WAVEFORM_CHANNEL_HEIGHT as isize - 1) as usize;
+        for (x, column) in self.columns.iter().enumerate() {
+            for (channel, levels) in column.iter().enumerate() {
+                let top = channel*WAVEFORM_CHANNEL_HEIGHT;
+                let clipped = levels.min <= -1.0 || levels.max >= 1.0;
+                for row in row_of(levels.max)..=row_of(levels.min) {
+                    pixels[(top + row)*width + x] = if clipped {WAVEFORM_CLIPPED} else {WAVEFORM_PEAK};
+                }
+                if !clipped {
+                    for row in row_of(levels.rms)..=row_of(-levels.rms) {
+                        pixels[(top + row)*width + x] = WAVEFORM_RMS;
+                    }
+                }
+                let axis = (top + half as usize)*width + x;
+                if pixels[axis] == WAVEFORM_BACKGROUND {
+                    pixels[axis] = WAVEFORM_AXIS;
+                }
+            }
+        }
+        mark_seconds(&mut pixels, width, fps);
+        write_ppm(sink, width, height, &pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    /// Width, height and pixels of a PPM written by [`write_ppm`]
+    fn read_ppm(bytes: &[u8]) -> (usize, usize, Vec<u32>) {
+        let mut lines = bytes.splitn(4, |byte| *byte == b'\n');
+        assert_eq!(lines.next(), Some(&b"P6"[..]));
+        let size = std::str::from_utf8(lines.next().unwrap()).unwrap();
+        let (width, height) = size.split_once(' ').unwrap();
+        let (width, height) = (width.parse().unwrap(), height.parse().unwrap());
+        assert_eq!(lines.next(), Some(&b"255"[..]));
+        let data = lines.next().unwrap();
+        assert_eq!(data.len(), width*height*3);
+        let pixels = data.chunks_exact(3).map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]])).collect();
+        (width, height, pixels)
+    }
+
+    /// Stereo sine with the same samples in both channels
+    fn sine(freq: f32, amplitude: f32, frames: usize) -> Vec<f32> {
+        use std::f32::consts::PI;
+        (0..frames)
+            .map(|n| amplitude*(2.0*PI*freq*n as f32/SAMPLE_RATE as f32).sin())
+            .flat_map(|x| [x, x])
+            .collect()
+    }
+
+    fn brightness(color: u32) -> u32 {
+        (color >> 16) + ((color >> 8) & 0xFF) + (color & 0xFF)
+    }
+
+    #[test]
+    fn ppm_header() {
+        let mut bytes = Vec::new();
+        write_ppm(&mut bytes, 2, 1, &[0x102030, 0xFFFFFF]).unwrap();
+        assert_eq!(bytes, b"P6\n2 1\n255\n\x10\x20\x30\xFF\xFF\xFF");
+    }
+
+    #[test]
+    fn spectrogram_of_a_sine() {
+        let freq = 1000.0;
+        let mut spectrogram = Spectrogram::new(SAMPLE_RATE);
+        for frame in sine(freq, 0.5, 10*800).chunks(2*800) {
+            spectrogram.frame(frame, 2);
+        }
+        let mut bytes = Vec::new();
+        spectrogram.write(&mut bytes, Rational::new(60, 1)).unwrap();
+        let (width, height, pixels) = read_ppm(&bytes);
+        assert_eq!((width, height), (10, SPECTROGRAM_HEIGHT));
+
+        // The row the frequency falls into, counted from the bottom
+        let nyquist = SAMPLE_RATE as f32/2.0;
+        let row = ((freq/SPECTROGRAM_MIN_FREQ).ln()/(nyquist/SPECTROGRAM_MIN_FREQ).ln()*SPECTROGRAM_HEIGHT as f32) as usize;
+        let x = width - 1;
+        let peak = (0..height).max_by_key(|y| brightness(pixels[y*width + x])).unwrap();
+        assert!((height - 1 - peak).abs_diff(row) <= 1, "peak at row {} instead of {row}", height - 1 - peak);
+        // Much darker far away from it
+        let peak = brightness(pixels[peak*width + x]);
+        for y in [0, height - 1] {
+            assert!(brightness(pixels[y*width + x]) < peak/4, "row {y} is {:06X}", pixels[y*width + x]);
+        }
+    }
+
+    #[test]
+    fn waveform_marks_the_clipped_frames() {
+        let mut waveform = Waveform::new(2);
+        waveform.frame(&sine(1000.0, 0.5, 800));
+        // Only the left channel clips
+        let mut clipped = sine(1000.0, 0.5, 800);
+        clipped[100] = 1.0;
+        waveform.frame(&clipped);
+        waveform.frame(&[0.0; 1600]);
+        let mut bytes = Vec::new();
+        waveform.write(&mut bytes, Rational::new(60, 1)).unwrap();
+        let (width, height, pixels) = read_ppm(&bytes);
+        assert_eq!((width, height), (3, 2*WAVEFORM_CHANNEL_HEIGHT));
+
+        let column = |x: usize, channel: usize| -> Vec<u32> {
+            (0..WAVEFORM_CHANNEL_HEIGHT).map(|row| pixels[(channel*WAVEFORM_CHANNEL_HEIGHT + row)*width + x]).collect()
+        };
+        assert!(column(1, 0).contains(&WAVEFORM_CLIPPED));
+        assert!(!column(1, 0).contains(&WAVEFORM_RMS));
+        for (x, channel) in [(0, 0), (0, 1), (1, 1), (2, 0), (2, 1)] {
+            assert!(!column(x, channel).contains(&WAVEFORM_CLIPPED), "frame {x} channel {channel}");
+            assert!(column(x, channel).contains(&WAVEFORM_RMS), "frame {x} channel {channel}");
+        }
+        // Half scale peaks a quarter of the channel away from the middle
+        let peak = column(0, 0).iter().position(|color| *color == WAVEFORM_PEAK).unwrap();
+        assert_eq!(peak, WAVEFORM_CHANNEL_HEIGHT/4);
+    }
+
+    #[test]
+    fn seconds_at_fractional_frame_rates() {
+        let width = 1000;
+        let mark = lerp_color(0, 0xFFFFFF, SECOND_MARK);
+        for (num, den) in [(24, 1), (30000, 1001), (25, 2)] {
+            let mut pixels = vec![0; width*2];
+            mark_seconds(&mut pixels, width, Rational::new(num, den));
+            // The first frame of second k starts at or after k*num/den
+            let expected: Vec<usize> = (1..).map(|k: usize| (k*num).div_ceil(den)).take_while(|frame| *frame < width).collect();
+            let marked: Vec<usize> = (0..width).filter(|x| pixels[*x] == mark).collect();
+            assert_eq!(marked, expected, "{num}/{den}");
+            assert!((0..width).all(|x| pixels[width + x] == pixels[x]));
+        }
+    }
+}
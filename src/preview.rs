@@ -7,11 +7,23 @@
 // At least see what needs to be done to make this work on windows
 // and create corresponding TODOs
 
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 use std::ffi::{c_void, CString, CStr};
-use std::ptr::{null, null_mut};
+use std::ptr::null_mut;
 use std::os::raw::{c_char, c_int, c_float, c_uint, c_double};
 use std::str;
+use std::env;
+use std::io::{self, Write};
 use super::config::*;
+use super::scene::Scene;
+use super::audio::SampleClock;
+use super::overlay::Layers;
+use super::playback::{self, AudioOutput, Backend};
+use super::sim::State;
 
 type GLFWwindow = c_void;
 type GLFWmonitor = c_void;
@@ -162,103 +174,171 @@ unsafe fn link_shaders_into_program(shaders: &[GLuint]) -> GLuint {
     program
 }
 
-#[repr(C)]
-#[allow(dead_code)]
-enum pa_stream_direction {
-    PA_STREAM_NODIRECTION,
-    PA_STREAM_PLAYBACK,
-    PA_STREAM_RECORD,
-    PA_STREAM_UPLOAD
-}
+const DELTA_TIME: f32 = FPS.den as f32 / FPS.num as f32;
+
+/// How far the simulation runs ahead of the sound being heard, seconds.
+/// Stays below the buffers of the outputs so writing the sound never blocks.
+/// The outputs start playing once that much is written.
+const AUDIO_AHEAD: f64 = 0.05;
+
+/// How long the audio thread sleeps between topping up the sound, seconds
+const AUDIO_POLL: f64 = 0.005;
 
-#[repr(C)]
-#[allow(dead_code)]
-enum pa_sample_format {
-    PA_SAMPLE_U8,
-    PA_SAMPLE_ALAW,
-    PA_SAMPLE_ULAW,
-    PA_SAMPLE_S16LE,
-    PA_SAMPLE_S16BE,
-    PA_SAMPLE_FLOAT32LE,
-    PA_SAMPLE_FLOAT32BE,
-    PA_SAMPLE_S32LE,
-    PA_SAMPLE_S32BE,
-    PA_SAMPLE_S24LE,
-    PA_SAMPLE_S24BE,
-    PA_SAMPLE_S24_32LE,
-    PA_SAMPLE_S24_32BE,
-    PA_SAMPLE_MAX,
-    PA_SAMPLE_INVALID = -1,
+/// Frame of the simulation waiting for its sound to be heard
+struct Frame {
+    canvas: Vec<u32>,
+    /// Seconds from the start of the sound
+    time: f64,
+    shown: bool,
 }
 
-#[repr(C)]
-struct pa_sample_spec {
-    format: pa_sample_format,
-    rate: u32,
-    channels: u8,
+/// Frames passed from the audio thread to the display
+#[derive(Default)]
+struct Frames {
+    /// The first one is being heard, the rest are waiting for their sound
+    queue: VecDeque<Frame>,
+    /// Canvases of the frames that were heard, reused for the next ones
+    pool: Vec<Vec<u32>>,
 }
 
-type pa_simple = *mut c_void;
-type pa_channel_map = *mut c_void;
-type pa_buffer_attr = *mut c_void;
+/// Runs the simulation at the pace of the sound `output` until `quit` is
+/// set. It lives on its own thread, so a slow or stalled display doesn't
+/// let the sound run out, it only gets frames skipped.
+fn play(scene: &Scene, output: &mut dyn AudioOutput, frames: &Mutex<Frames>, quit: &AtomicBool) -> io::Result<()> {
+    let mut state = State::new(WIDTH as f32, HEIGHT as f32, scene);
+    let mut sound = Vec::new();
+    let mut clock = SampleClock::new(SOUND_SAMPLE_RATE, FPS);
+    let mut overlays = Layers::new(&scene.overlays, SOUND_SAMPLE_RATE);
+
+    let mut written = 0;
+    let mut playing = false;
+    let mut underruns = 0;
+    let mut dropped = 0;
+    let mut reported_dropped = 0;
+    let mut reported_at = 0.0;
+    while !quit.load(Ordering::Relaxed) {
+        let latency = output.latency()?;
+        let written_time = written as f64/SOUND_SAMPLE_RATE as f64;
+        if playing && latency <= 0.0 {
+            underruns += 1;
+            eprintln!("WARNING: the sound ran out at {written_time:.3}s");
+        }
 
-#[link(name = "pulse-simple")]
-#[link(name = "pulse")]
-extern "C" {
-    fn pa_simple_new(server: *const c_char, name: *const c_char,
-                     dir: pa_stream_direction,
-                     dev: *const c_char,
-                     stream_name: *const c_char,
-                     ss: *const pa_sample_spec,
-                     map: *const pa_channel_map,
-                     attr: *const pa_buffer_attr,
-                     error: *mut c_int) -> *mut pa_simple;
-    fn pa_simple_write(s: *mut pa_simple,
-                       data: *const c_void,
-                       bytes: u64,
-                       error: *mut c_int) -> c_int;
-    fn pa_strerror(error: c_int) -> *const c_char;
-}
+        let mut ahead = latency;
+        while ahead < AUDIO_AHEAD {
+            let samples = clock.next_frame();
 
-const DELTA_TIME: f32 = 1.0 / FPS as f32;
+            // The overlays show the sound of the frame, so it goes first
+            sound.clear();
+            sound.resize(samples*SOUND_CHANNELS, 0.0);
+            state.sound(&mut sound, SOUND_SAMPLE_RATE);
 
-pub fn main() {
-    use super::sim::*;
+            let canvas = frames.lock().unwrap().pool.pop();
+            let mut canvas = canvas.unwrap_or_else(|| vec![0; WIDTH * HEIGHT]);
+            canvas.fill(BACKGROUND);
+            state.render(&mut canvas, WIDTH);
+            overlays.draw(&mut canvas, WIDTH, &sound, SOUND_CHANNELS);
+
+            output.write(&sound)?;
+            frames.lock().unwrap().queue.push_back(Frame {
+                canvas,
+                time: written as f64/SOUND_SAMPLE_RATE as f64,
+                shown: false,
+            });
+            written += samples;
+            ahead += samples as f64/SOUND_SAMPLE_RATE as f64;
+
+            state.update(DELTA_TIME);
+        }
+        playing = true;
+
+        // Frames the display was too slow to show before their sound
+        // was over are skipped
+        let playhead = written as f64/SOUND_SAMPLE_RATE as f64 - ahead;
+        {
+            let mut frames = frames.lock().unwrap();
+            let Frames {queue, pool} = &mut *frames;
+            while queue.len() > 1 && queue[1].time <= playhead {
+                let frame = queue.pop_front().unwrap();
+                if !frame.shown {
+                    dropped += 1;
+                }
+                pool.push(frame.canvas);
+            }
+        }
+        if playhead - reported_at >= 1.0 {
+            if dropped > reported_dropped {
+                eprintln!("WARNING: dropped {} frames in the last {:.1}s", dropped - reported_dropped, playhead - reported_at);
+            }
+            reported_dropped = dropped;
+            reported_at = playhead;
+        }
 
-    let mut state = State::new(WIDTH as f32, HEIGHT as f32);
-    let mut canvas = vec![0; WIDTH * HEIGHT];
-    let mut sound = vec![0.0; (DELTA_TIME * SOUND_SAMPLE_RATE as f32).floor() as usize];
+        thread::sleep(Duration::from_secs_f64(AUDIO_POLL));
+    }
 
-    unsafe {
-        use self::pa_stream_direction::*;
-        use self::pa_sample_format::*;
-
-        let mut args = std::env::args();
-        let program = CString::new(args.next().expect("Program name")).unwrap();
-        let stream_name = CString::new("playback").unwrap();
-
-        let ss = pa_sample_spec {
-            format: PA_SAMPLE_FLOAT32LE,
-            rate: SOUND_SAMPLE_RATE as u32,
-            channels: 1,
-        };
-
-        let mut error: c_int = 0;
-
-        let s = pa_simple_new(null_mut(),
-                              program.as_ptr(),
-                              PA_STREAM_PLAYBACK,
-                              null_mut(),
-                              stream_name.as_ptr(),
-                              &ss,
-                              null(),
-                              null(),
-                              &mut error);
-        if s.is_null() {
-            panic!("pa_simple_new() failed: {}", CStr::from_ptr(pa_strerror(error)).to_str().unwrap());
+    println!("Played {:.1}s of sound with {underruns} underruns and {dropped} dropped frames",
+             written as f64/SOUND_SAMPLE_RATE as f64);
+    output.finish()
+}
+
+fn usage(output: &mut impl Write, program_name: &str) -> io::Result<()> {
+    writeln!(output, "Usage: {program_name} preview [OPTIONS]")?;
+    writeln!(output, "OPTIONS:")?;
+    writeln!(output, "    -scene <path>    load the settings of the scene from the file (default: built-in scene)")?;
+    writeln!(output, "    -audio-output <auto|pulse|alsa|null|wav:path>")?;
+    writeln!(output, "                     where to play the sound, the WAV file saves it instead (default: auto, the first of pulse, alsa and null)")?;
+    writeln!(output, "    -help            print this help message and exit")?;
+    Ok(())
+}
+
+struct Options {
+    scene: Scene,
+    audio_output: Backend,
+}
+
+fn parse_options(program_name: &str, mut args: env::Args) -> Options {
+    let fail = |message: String| -> ! {
+        usage(&mut io::stderr(), program_name).unwrap();
+        eprintln!("ERROR: {message}");
+        std::process::exit(1);
+    };
+    let mut options = Options {
+        scene: Scene::default(),
+        audio_output: Backend::default(),
+    };
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-scene" => {
+                let Some(path) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.scene = Scene::load(&path).unwrap_or_else(|err| fail(err.to_string()));
+            }
+            "-audio-output" => {
+                let Some(backend) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.audio_output = backend.parse().unwrap_or_else(|err| fail(err));
+            }
+            "-help" => {
+                usage(&mut io::stdout(), program_name).unwrap();
+                std::process::exit(0);
+            }
+            _ => fail(format!("unknown flag {flag}")),
         }
+    }
+    options
+}
+
+pub fn main(program_name: &str, args: env::Args) -> io::Result<()> {
+    let options = parse_options(program_name, args);
+    // The frame on the display, copied out of the queue of the audio thread
+    let mut shown = vec![0; WIDTH * HEIGHT];
+    let frames = Mutex::new(Frames::default());
+    let quit = AtomicBool::new(false);
 
-        println!("Created the playback stream: {:?}", s);
+    let mut output = playback::open(&options.audio_output, program_name, SOUND_SAMPLE_RATE, SOUND_CHANNELS, AUDIO_AHEAD)?;
+    println!("Playing the sound through {}", output.name());
+
+    thread::scope(|s| unsafe {
+        let audio = s.spawn(|| play(&options.scene, output.as_mut(), &frames, &quit));
 
         glfwSetErrorCallback(glfw_error_callback);
 
@@ -297,7 +377,7 @@ pub fn main() {
                      0,
                      GL_RGBA,
                      GL_UNSIGNED_BYTE,
-                     canvas.as_ptr() as *const GLvoid);
+                     shown.as_ptr() as *const GLvoid);
 
         let vert_shader = compile_shader_from_source(
             GL_VERTEX_SHADER,
@@ -345,26 +425,30 @@ pub fn main() {
 
         glUseProgram(program);
 
-        while glfwWindowShouldClose(window) == 0 {
+        // The simulation follows the clock of the sound device on the audio
+        // thread, the display only shows whichever frame is being heard
+        while glfwWindowShouldClose(window) == 0 && !audio.is_finished() {
             glfwPollEvents();
 
-            canvas.fill(BACKGROUND);
-            state.render(&mut canvas, WIDTH);
-            glTexSubImage2D(GL_TEXTURE_2D,
-                             0,
-                             0,
-                             0,
-                             WIDTH as GLsizei,
-                             HEIGHT as GLsizei,
-                             GL_RGBA,
-                             GL_UNSIGNED_BYTE,
-                             canvas.as_ptr() as *const GLvoid);
-
-            sound.fill(0.0);
-            state.sound(&mut sound, SOUND_SAMPLE_RATE);
-            pa_simple_write(s, sound.as_ptr() as *const c_void, 4 * sound.len() as u64, &mut error);
-
-            state.update(1.0 / 60.0);
+            let mut fresh = false;
+            if let Some(frame) = frames.lock().unwrap().queue.front_mut() {
+                if !frame.shown {
+                    shown.copy_from_slice(&frame.canvas);
+                    frame.shown = true;
+                    fresh = true;
+                }
+            }
+            if fresh {
+                glTexSubImage2D(GL_TEXTURE_2D,
+                                 0,
+                                 0,
+                                 0,
+                                 WIDTH as GLsizei,
+                                 HEIGHT as GLsizei,
+                                 GL_RGBA,
+                                 GL_UNSIGNED_BYTE,
+                                 shown.as_ptr() as *const GLvoid);
+            }
 
             glUniform1f(time_uniform_location, glfwGetTime() as f32);
             glClearColor(0.0, 0.0, 0.0, 1.0);
@@ -374,5 +458,32 @@ pub fn main() {
         }
 
         glfwTerminate();
+        quit.store(true, Ordering::Relaxed);
+        audio.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sound_goes_on_without_the_display() {
+        let mut output = playback::open(&Backend::Null, "test", SOUND_SAMPLE_RATE, SOUND_CHANNELS, AUDIO_AHEAD).unwrap();
+        let frames = Mutex::new(Frames::default());
+        let quit = AtomicBool::new(false);
+        let scene = Scene::default();
+        thread::scope(|s| {
+            let audio = s.spawn(|| play(&scene, output.as_mut(), &frames, &quit));
+            // Nobody shows the frames, they are still played and skipped
+            thread::sleep(Duration::from_millis(300));
+            quit.store(true, Ordering::Relaxed);
+            audio.join().unwrap().unwrap();
+        });
+        let frames = frames.into_inner().unwrap();
+        let lead = (AUDIO_AHEAD*FPS.to_f32() as f64).ceil() as usize + 2;
+        assert!(frames.queue.len() <= lead, "{} frames are queued", frames.queue.len());
+        assert!(frames.queue[0].time > 0.2, "the sound stopped at {}s", frames.queue[0].time);
+        assert!(!frames.pool.is_empty());
     }
 }
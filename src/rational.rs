@@ -0,0 +1,115 @@
+//! Rational numbers for time bases and aspect ratios
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rational {
+    pub num: usize,
+    pub den: usize,
+}
+
+fn gcd(mut a: usize, mut b: usize) -> usize {
+    while b != 0 {
+        (a, b) = (b, a%b);
+    }
+    a
+}
+
+impl Rational {
+    pub const fn new(num: usize, den: usize) -> Self {
+        Self {num, den}
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.num as f32 / self.den as f32
+    }
+
+    pub fn recip(self) -> Self {
+        Self::new(self.den, self.num)
+    }
+
+    pub fn reduce(self) -> Self {
+        let d = gcd(self.num, self.den).max(1);
+        Self::new(self.num/d, self.den/d)
+    }
+
+    /// `floor(self * x)` computed without going through floating point
+    pub fn mul_floor(self, x: usize) -> usize {
+        (x as u128 * self.num as u128 / self.den as u128) as usize
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+/// Accepts `N`, `N/D` and `N:D`
+impl FromStr for Rational {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (num, den) = s.split_once(['/', ':']).unwrap_or((s, "1"));
+        let num = num.parse().map_err(|err| format!("invalid numerator in {s}: {err}"))?;
+        let den = den.parse().map_err(|err| format!("invalid denominator in {s}: {err}"))?;
+        if num == 0 || den == 0 {
+            return Err(format!("{s} must be positive"));
+        }
+        Ok(Self::new(num, den))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for (s, num, den) in [("30", 30, 1), ("30000/1001", 30000, 1001), ("16:9", 16, 9), ("60/2", 60, 2), ("007:1", 7, 1)] {
+            assert_eq!(s.parse(), Ok(Rational::new(num, den)), "{s}");
+        }
+    }
+
+    #[test]
+    fn rejects() {
+        for s in [
+            "", "0", "0/1", "1/0", "0:0", "-1", "1/-2", "30.5", "1/2/3", "1:2/3", "/", "1/", ":2",
+            "fps", " 30", "30 ", "30 / 1", "1e3", "99999999999999999999999/1",
+        ] {
+            assert!(s.parse::<Rational>().is_err(), "{s:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let rational = Rational::new(30000, 1001);
+        assert_eq!(rational.to_string(), "30000/1001");
+        assert_eq!(rational.to_string().parse(), Ok(rational));
+    }
+
+    #[test]
+    fn reduce() {
+        assert_eq!(Rational::new(6, 4).reduce(), Rational::new(3, 2));
+        assert_eq!(Rational::new(60000, 2002).reduce(), Rational::new(30000, 1001));
+        assert_eq!(Rational::new(30000, 1001).reduce(), Rational::new(30000, 1001));
+        assert_eq!(Rational::new(48000, 48000).reduce(), Rational::new(1, 1));
+        assert_eq!(Rational::new(0, 5).reduce(), Rational::new(0, 1));
+    }
+
+    #[test]
+    fn mul_floor() {
+        let ntsc = Rational::new(30000, 1001);
+        assert_eq!(ntsc.mul_floor(1001), 30000);
+        assert_eq!(ntsc.mul_floor(1000), 29970);
+        assert_eq!(ntsc.recip().mul_floor(1_000_000), 33366);
+        assert_eq!(Rational::new(3, 2).mul_floor(0), 0);
+        // Doesn't overflow in the middle
+        assert_eq!(Rational::new(usize::MAX, usize::MAX).mul_floor(usize::MAX), usize::MAX);
+        assert_eq!(Rational::new(1, 2).mul_floor(usize::MAX), usize::MAX/2);
+        // Exact even where f32 runs out of digits
+        let fps = Rational::new(24000, 1001);
+        assert_eq!(fps.mul_floor(3600*1001), 3600*24000);
+        assert_eq!(fps.mul_floor(3600*1001 - 1), 3600*24000 - 24);
+    }
+}
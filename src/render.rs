@@ -1,52 +1,520 @@
+use std::env;
 use std::fs::File;
 use std::io;
-use std::io::{Write, BufWriter};
+use std::io::{Write, Seek, SeekFrom, BufWriter};
 use crate::sim::*;
 use crate::config::*;
 use crate::avi;
 use crate::yuv4mpeg2;
+use crate::wav;
+use crate::midi;
+use crate::loudness;
+use crate::plot::{Spectrogram, Waveform};
+use crate::overlay::Layers;
+use crate::track::Track;
+use crate::audio::SampleClock;
+use crate::resample::{self, Resampler};
+use crate::scene::Scene;
+use crate::rational::Rational;
 
-const DELTA_TIME: f32 = 1.0 / FPS as f32;
 const VIDEO_DURATION: f32 = 6.0;
 const VIDEO_OUTPUT_PATH: &str = "output.y4m";
-const AUDIO_OUTPUT_PATH: &str = "output.pcm";
+const AUDIO_OUTPUT_PATH: &str = "output.wav";
+const AVI_OUTPUT_PATH: &str = "output.avi";
+const STDOUT_PATH: &str = "-";
 
-pub fn main() -> io::Result<()> {
-    let frames_count: usize = (FPS as f32 * VIDEO_DURATION).floor() as usize;
+struct Options {
+    video_output_path: String,
+    audio_output_path: String,
+    avi_output_path: Option<String>,
+    midi_output_path: Option<String>,
+    spectrogram_output_path: Option<String>,
+    waveform_output_path: Option<String>,
+    /// Target integrated loudness, LUFS
+    loudness: Option<f32>,
+    /// Sample rates of the WAV and AVI audio. The sound is synthesized at
+    /// [`SOUND_SAMPLE_RATE`] and resampled for the outputs that differ.
+    audio_rate: usize,
+    avi_audio_rate: usize,
+    resample_quality: resample::Quality,
+    fps: Rational,
+    pixel_aspect: Rational,
+    interlace: Interlace,
+    alpha: bool,
+    /// The streams that are generated and written, the others are skipped
+    streams: Streams,
+    scene: Scene,
+    /// Mixed into the sound of the simulation
+    track: Option<Track>,
+    /// Drop the sound of the simulation and keep only the track
+    track_replace: bool,
+    /// Render until the end of the track instead of [`VIDEO_DURATION`]
+    track_length: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            video_output_path: VIDEO_OUTPUT_PATH.to_string(),
+            audio_output_path: AUDIO_OUTPUT_PATH.to_string(),
+            avi_output_path: Some(AVI_OUTPUT_PATH.to_string()),
+            midi_output_path: None,
+            spectrogram_output_path: None,
+            waveform_output_path: None,
+            loudness: None,
+            audio_rate: SOUND_SAMPLE_RATE,
+            avi_audio_rate: SOUND_SAMPLE_RATE,
+            resample_quality: resample::Quality::default(),
+            fps: FPS,
+            pixel_aspect: PIXEL_ASPECT,
+            interlace: INTERLACE,
+            alpha: false,
+            streams: Streams::default(),
+            scene: Scene::default(),
+            track: None,
+            track_replace: false,
+            track_length: false,
+        }
+    }
+}
+
+fn usage(output: &mut impl Write, program_name: &str) -> io::Result<()> {
+    writeln!(output, "Usage: {program_name} render [OPTIONS]")?;
+    writeln!(output, "OPTIONS:")?;
+    writeln!(output, "    -video <path>    where to write the YUV4MPEG2 video (default: {VIDEO_OUTPUT_PATH}, `{STDOUT_PATH}` for stdout)")?;
+    writeln!(output, "    -audio <path>    where to write the audio, can be a named pipe (default: {AUDIO_OUTPUT_PATH}, `{STDOUT_PATH}` for stdout)")?;
+    writeln!(output, "    -avi <path>      where to write the AVI file (default: {AVI_OUTPUT_PATH})")?;
+    writeln!(output, "    -no-avi          do not generate the AVI file")?;
+    writeln!(output, "    -midi <path>     also write the notes of the beeps that are heard into a Standard MIDI File (`{STDOUT_PATH}` for stdout)")?;
+    writeln!(output, "    -spectrogram <path>")?;
+    writeln!(output, "                     also draw the spectrogram of the audio into a PPM image with a column per frame")?;
+    writeln!(output, "    -waveform <path> also draw the levels of the audio into a PPM image with a column per frame")?;
+    writeln!(output, "    -loudness <LUFS> normalize the integrated loudness of the audio to the target in two passes, e.g. -16")?;
+    writeln!(output, "    -audio-rate <Hz> sample rate of the audio file (default: {SOUND_SAMPLE_RATE})")?;
+    writeln!(output, "    -avi-audio-rate <Hz>")?;
+    writeln!(output, "                     sample rate of the audio in the AVI file (default: {SOUND_SAMPLE_RATE})")?;
+    writeln!(output, "    -resample-quality <low|medium|high>")?;
+    writeln!(output, "                     quality of the sample rate conversion for the outputs above (default: medium)")?;
+    writeln!(output, "    -fps <rate>      frame rate as N or N/D, e.g. 30000/1001 (default: {FPS})")?;
+    writeln!(output, "    -pixel-aspect <ratio>")?;
+    writeln!(output, "                     pixel aspect ratio as N:D (default: {PIXEL_ASPECT})")?;
+    writeln!(output, "    -interlace <progressive|top|bottom>")?;
+    writeln!(output, "                     render two fields per frame woven in top or bottom first order (default: progressive)")?;
+    writeln!(output, "    -alpha           render on a transparent background and keep the alpha channel (C444alpha, BGRA)")?;
+    writeln!(output, "    -audio-only      only generate the audio, skipping the drawing, no video file is written")?;
+    writeln!(output, "    -video-only      only generate the video, no audio file is written")?;
+    writeln!(output, "    -scene <path>    load the settings of the scene from the file (default: built-in scene)")?;
+    writeln!(output, "    -track <path>    mix the WAV file into the audio, resampled to {SOUND_SAMPLE_RATE} Hz")?;
+    writeln!(output, "    -track-gain <dB> gain of the track (default: 0)")?;
+    writeln!(output, "    -track-offset <seconds>")?;
+    writeln!(output, "                     when the track starts, negative values skip its beginning (default: 0)")?;
+    writeln!(output, "    -track-replace   replace the sound of the simulation with the track instead of mixing them")?;
+    writeln!(output, "    -track-length    render until the track ends instead of {VIDEO_DURATION} seconds")?;
+    writeln!(output, "    -help            print this help message and exit")?;
+    Ok(())
+}
+
+fn parse_options(program_name: &str, mut args: env::Args) -> Options {
+    let mut options = Options::default();
+    let mut track_path = None;
+    let mut track_gain: f32 = 0.0;
+    let mut track_offset: f64 = 0.0;
+    let fail = |message: String| -> ! {
+        usage(&mut io::stderr(), program_name).unwrap();
+        eprintln!("ERROR: {message}");
+        std::process::exit(1);
+    };
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-video" => {
+                let Some(path) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.video_output_path = path;
+            }
+            "-audio" => {
+                let Some(path) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.audio_output_path = path;
+            }
+            "-avi" => {
+                let Some(path) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.avi_output_path = Some(path);
+            }
+            "-no-avi" => options.avi_output_path = None,
+            "-midi" => {
+                let Some(path) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.midi_output_path = Some(path);
+            }
+            "-spectrogram" => {
+                let Some(path) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.spectrogram_output_path = Some(path);
+            }
+            "-waveform" => {
+                let Some(path) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.waveform_output_path = Some(path);
+            }
+            "-loudness" => {
+                let Some(loudness) = args.next() else { fail(format!("no value provided for {flag}")) };
+                let target: f32 = loudness.parse().unwrap_or_else(|err| fail(format!("invalid loudness {loudness}: {err}")));
+                if !target.is_finite() {
+                    fail(format!("invalid loudness {loudness}, it must be finite"));
+                }
+                options.loudness = Some(target);
+            }
+            "-audio-rate" | "-avi-audio-rate" => {
+                let Some(rate) = args.next() else { fail(format!("no value provided for {flag}")) };
+                let rate = rate.parse().ok().filter(|rate| *rate > 0).unwrap_or_else(|| fail(format!("invalid sample rate {rate}")));
+                if flag == "-audio-rate" {
+                    options.audio_rate = rate;
+                } else {
+                    options.avi_audio_rate = rate;
+                }
+            }
+            "-resample-quality" => {
+                let Some(quality) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.resample_quality = quality.parse().unwrap_or_else(|err| fail(err));
+            }
+            "-fps" => {
+                let Some(fps) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.fps = fps.parse().unwrap_or_else(|err| fail(err));
+            }
+            "-pixel-aspect" => {
+                let Some(pixel_aspect) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.pixel_aspect = pixel_aspect.parse().unwrap_or_else(|err| fail(err));
+            }
+            "-alpha" => options.alpha = true,
+            "-audio-only" | "-video-only" if options.streams != Streams::Both => {
+                fail("-audio-only and -video-only can't be used together".to_string());
+            }
+            "-audio-only" => options.streams = Streams::AudioOnly,
+            "-video-only" => options.streams = Streams::VideoOnly,
+            "-interlace" => {
+                let Some(interlace) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.interlace = interlace.parse().unwrap_or_else(|err| fail(err));
+            }
+            "-scene" => {
+                let Some(path) = args.next() else { fail(format!("no value provided for {flag}")) };
+                options.scene = Scene::load(&path).unwrap_or_else(|err| fail(err.to_string()));
+            }
+            "-track" => {
+                let Some(path) = args.next() else { fail(format!("no value provided for {flag}")) };
+                track_path = Some(path);
+            }
+            "-track-gain" => {
+                let Some(gain) = args.next() else { fail(format!("no value provided for {flag}")) };
+                track_gain = gain.parse().unwrap_or_else(|err| fail(format!("invalid track gain {gain}: {err}")));
+                if !track_gain.is_finite() {
+                    fail(format!("invalid track gain {gain}, it must be finite"));
+                }
+            }
+            "-track-offset" => {
+                let Some(offset) = args.next() else { fail(format!("no value provided for {flag}")) };
+                track_offset = offset.parse().unwrap_or_else(|err| fail(format!("invalid track offset {offset}: {err}")));
+                if !track_offset.is_finite() {
+                    fail(format!("invalid track offset {offset}, it must be finite"));
+                }
+            }
+            "-track-replace" => options.track_replace = true,
+            "-track-length" => options.track_length = true,
+            "-help" => {
+                usage(&mut io::stdout(), program_name).unwrap();
+                std::process::exit(0);
+            }
+            _ => fail(format!("unknown flag {flag}")),
+        }
+    }
+    let stdout_outputs = [
+        Some(&options.video_output_path).filter(|_| options.streams.has_video()),
+        Some(&options.audio_output_path).filter(|_| options.streams.has_audio()),
+        options.midi_output_path.as_ref(),
+        options.spectrogram_output_path.as_ref(),
+        options.waveform_output_path.as_ref(),
+    ]
+        .into_iter()
+        .filter(|path| path.is_some_and(|path| path == STDOUT_PATH))
+        .count();
+    if stdout_outputs > 1 {
+        fail("only one output can be written to stdout".to_string());
+    }
+    if let Some(path) = track_path {
+        let wav = wav::read(&path).unwrap_or_else(|err| fail(format!("{path}: {err}")));
+        options.track = Some(Track::new(&wav, SOUND_SAMPLE_RATE, SOUND_CHANNELS, options.resample_quality, track_gain, track_offset));
+    } else if options.track_replace || options.track_length {
+        fail("-track-replace and -track-length need a -track".to_string());
+    }
+    if !options.streams.has_audio() {
+        let audio_flags = [
+            ("-loudness", options.loudness.is_some()),
+            ("-track", options.track.is_some()),
+            ("-spectrogram", options.spectrogram_output_path.is_some()),
+            ("-waveform", options.waveform_output_path.is_some()),
+        ];
+        if let Some((flag, _)) = audio_flags.iter().find(|(_, set)| *set) {
+            fail(format!("{flag} needs the audio, it can't be used with -video-only"));
+        }
+    }
+    options
+}
+
+/// Either a file or stdout. Seeking stdout always fails, so the
+/// containers that patch their headers leave them as is there.
+enum Output {
+    File(File),
+    Stdout(io::StdoutLock<'static>),
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::File(file) => file.write(buf),
+            Output::Stdout(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::File(file) => file.flush(),
+            Output::Stdout(stdout) => stdout.flush(),
+        }
+    }
+}
+
+impl Seek for Output {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Output::File(file) => file.seek(pos),
+            Output::Stdout(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "can't seek stdout")),
+        }
+    }
+}
+
+/// Opens the output at `path` for writing. `-` stands for stdout.
+fn create_sink(path: &str) -> io::Result<BufWriter<Output>> {
+    let sink = if path == STDOUT_PATH {
+        Output::Stdout(io::stdout().lock())
+    } else {
+        Output::File(File::create(path)?)
+    };
+    Ok(BufWriter::new(sink))
+}
+
+fn report_generated(path: &str) {
+    if path != STDOUT_PATH {
+        eprintln!("Generated {}", path);
+    }
+}
+
+/// Replays the simulation without drawing anything to measure the loudness
+/// of its sound. The simulation is deterministic, so the render gets exactly
+/// the same sound.
+fn measure_loudness(options: &Options, field_rate: Rational, fields_count: usize) -> loudness::Loudness {
+    let scene = &options.scene;
+    let mut track = options.track.clone();
+    let delta_time = field_rate.recip().to_f32();
+    let mut state = State::new(WIDTH as f32, HEIGHT as f32, scene);
+    let mut meter = loudness::Meter::new(SOUND_SAMPLE_RATE, SOUND_CHANNELS);
+    let mut clock = SampleClock::new(SOUND_SAMPLE_RATE, field_rate);
+    let mut sound = Vec::new();
+    for _ in 0..fields_count {
+        sound.clear();
+        sound.resize(clock.next_frame()*SOUND_CHANNELS, 0.0);
+        state.sound(&mut sound, SOUND_SAMPLE_RATE);
+        if let Some(track) = track.as_mut() {
+            track.mix(&mut sound, options.track_replace);
+        }
+        state.update(delta_time);
+        meter.process(&sound);
+    }
+    meter.loudness()
+}
+
+/// Copies every other line of `field` into `canvas` starting from `first_line`
+fn weave_field(canvas: &mut [u32], field: &[u32], stride: usize, first_line: usize) {
+    let lines = canvas.chunks_mut(stride).zip(field.chunks(stride));
+    for (canvas_line, field_line) in lines.skip(first_line).step_by(2) {
+        canvas_line.copy_from_slice(field_line);
+    }
+}
+
+pub fn main(program_name: &str, args: env::Args) -> io::Result<()> {
+    let mut options = parse_options(program_name, args);
+    // Interlaced video is simulated and rendered once per field
+    let fields_per_frame = options.interlace.fields_per_frame();
+    let field_rate = Rational::new(options.fps.num*fields_per_frame, options.fps.den);
+    let delta_time = field_rate.recip().to_f32();
+
+    let frames_count: usize = match &options.track {
+        Some(track) if options.track_length => {
+            (track.end(SOUND_SAMPLE_RATE)*options.fps.num as f64/options.fps.den as f64).ceil() as usize
+        }
+        _ => (options.fps.to_f32() * VIDEO_DURATION).floor() as usize,
+    };
     let mut canvas = vec![0; WIDTH*HEIGHT];
-    let mut sound = vec![0.0; (DELTA_TIME * SOUND_SAMPLE_RATE as f32).floor() as usize];
-    let mut video_sink = BufWriter::new(File::create(VIDEO_OUTPUT_PATH)?);
-    let mut audio_sink = BufWriter::new(File::create(AUDIO_OUTPUT_PATH)?);
-    let mut state = State::new(WIDTH as f32, HEIGHT as f32);
+    let mut field_canvas = vec![0; WIDTH*HEIGHT];
+    let mut sound = Vec::new();
+    let streams = options.streams;
+    let mut video_sink = if streams.has_video() {Some(create_sink(&options.video_output_path)?)} else {None};
+    let mut audio_sink = if streams.has_audio() {
+        Some(wav::Writer::new(create_sink(&options.audio_output_path)?, options.audio_rate, SOUND_CHANNELS)?)
+    } else {
+        None
+    };
+    // The video needs the sound only for the overlays and the notes
+    let has_sound = streams.has_audio() || !options.scene.overlays.is_empty() || options.midi_output_path.is_some();
+    let mut audio_resampler = Resampler::new(SOUND_SAMPLE_RATE, options.audio_rate, SOUND_CHANNELS, options.resample_quality);
+    let mut avi_resampler = Resampler::new(SOUND_SAMPLE_RATE, options.avi_audio_rate, SOUND_CHANNELS, options.resample_quality);
+    let mut resampled = Vec::new();
+    let mut state = State::new(WIDTH as f32, HEIGHT as f32, &options.scene);
+    let mut overlays = Layers::new(&options.scene.overlays, SOUND_SAMPLE_RATE);
+    if options.midi_output_path.is_some() {
+        state.record_notes();
+    }
+    if !has_sound {
+        state.mute();
+    }
+    let mut meter = None;
+    if let Some(target) = options.loudness {
+        eprintln!("Measuring loudness...");
+        let measured = measure_loudness(&options, field_rate, frames_count*fields_per_frame);
+        eprintln!("Loudness before normalization:\n{measured}");
+        if measured.integrated.is_finite() {
+            let gain = 10f32.powf((target - measured.integrated as f32)/20.0);
+            state.set_gain(gain);
+            if let Some(track) = options.track.as_mut() {
+                track.set_gain(gain);
+            }
+        } else {
+            eprintln!("WARNING: the audio is silent, can't normalize its loudness");
+        }
+        meter = Some(loudness::Meter::new(SOUND_SAMPLE_RATE, SOUND_CHANNELS));
+    }
+    let mut spectrogram = options.spectrogram_output_path.as_ref().map(|_| Spectrogram::new(SOUND_SAMPLE_RATE));
+    let mut waveform = options.waveform_output_path.as_ref().map(|_| Waveform::new(SOUND_CHANNELS));
 
     let mut y4m2 = yuv4mpeg2::Container::default();
     let mut avi = avi::Container::default();
 
-    y4m2.start(&mut video_sink, WIDTH, HEIGHT, FPS)?;
-    avi.start(WIDTH, HEIGHT, FPS);
+    let format = VideoFormat {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: options.fps,
+        pixel_aspect: options.pixel_aspect,
+        interlace: options.interlace,
+        alpha: options.alpha,
+    };
+    let background = if options.alpha {TRANSPARENT} else {BACKGROUND};
+
+    let mut clock = SampleClock::new(SOUND_SAMPLE_RATE, field_rate);
+    if let Some(video_sink) = video_sink.as_mut() {
+        y4m2.start(video_sink, format)?;
+    }
+    avi.start(format, options.avi_audio_rate, streams);
     for frame_index in 0..frames_count {
-        canvas.fill(BACKGROUND);
-        state.render(&mut canvas, WIDTH);
+        sound.clear();
+        for field_index in 0..fields_per_frame {
+            if !streams.has_video() {
+                // Nothing to draw
+            } else if options.interlace == Interlace::Progressive {
+                canvas.fill(background);
+                state.render(&mut canvas, WIDTH);
+            } else {
+                field_canvas.fill(background);
+                state.render(&mut field_canvas, WIDTH);
+                weave_field(&mut canvas, &field_canvas, WIDTH, options.interlace.field_first_line(field_index));
+            }
 
-        sound.fill(0.0);
-        state.sound(&mut sound, SOUND_SAMPLE_RATE);
+            if has_sound {
+                // Every field gets its own share of the samples of the frame
+                let field_start = sound.len();
+                sound.resize(field_start + clock.next_frame()*SOUND_CHANNELS, 0.0);
+                state.sound(&mut sound[field_start..], SOUND_SAMPLE_RATE);
+                if let Some(track) = options.track.as_mut() {
+                    track.mix(&mut sound[field_start..], options.track_replace);
+                }
+            }
 
-        y4m2.frame(&mut video_sink, &canvas)?;
-        for sample in sound.iter() {
-            audio_sink.write(&sample.to_le_bytes())?;
+            state.update(delta_time);
         }
-        avi.frame(&canvas, &sound)?;
 
-        state.update(DELTA_TIME);
+        if let Some(video_sink) = video_sink.as_mut() {
+            overlays.draw(&mut canvas, WIDTH, &sound, SOUND_CHANNELS);
+            y4m2.frame(video_sink, &canvas)?;
+        }
+        if let Some(audio_sink) = audio_sink.as_mut() {
+            resampled.clear();
+            audio_resampler.process(&sound, &mut resampled);
+            audio_sink.write(&resampled)?;
+        }
+        if let Some(meter) = meter.as_mut() {
+            meter.process(&sound);
+        }
+        if let Some(spectrogram) = spectrogram.as_mut() {
+            spectrogram.frame(&sound, SOUND_CHANNELS);
+        }
+        if let Some(waveform) = waveform.as_mut() {
+            waveform.frame(&sound);
+        }
+        if options.avi_output_path.is_some() {
+            if streams.has_video() {
+                avi.video(&canvas)?;
+            }
+            if streams.has_audio() {
+                resampled.clear();
+                avi_resampler.process(&sound, &mut resampled);
+                avi.sound(&resampled)?;
+            }
+        }
 
+        // Progress goes to stderr so stdout stays free for streaming the video
         let progress = (frame_index as f32 / frames_count as f32 * 100.0).round() as usize;
-        print!("Progress {}%\r", progress);
-        io::stdout().flush()?;
+        eprint!("Progress {}%\r", progress);
+    }
+
+    if let Some(mut video_sink) = video_sink {
+        video_sink.flush()?;
+    }
+    if let Some(mut audio_sink) = audio_sink {
+        resampled.clear();
+        audio_resampler.finish(&mut resampled);
+        audio_sink.write(&resampled)?;
+        audio_sink.finish()?;
+    }
+
+    if let Some(avi_output_path) = &options.avi_output_path {
+        resampled.clear();
+        if streams.has_audio() {
+            avi_resampler.finish(&mut resampled);
+        }
+        if !resampled.is_empty() {
+            avi.sound(&resampled)?;
+        }
+        avi.finish(avi_output_path)?;
     }
 
-    avi.finish("output.avi")?;
+    if let Some(meter) = &meter {
+        // The limiter may keep the loudest parts from reaching the target
+        eprintln!("Loudness after normalization:\n{}", meter.loudness());
+    }
+
+    if let Some(midi_output_path) = &options.midi_output_path {
+        let bpm = options.scene.bpm.unwrap_or(midi::DEFAULT_BPM);
+        midi::write(&mut create_sink(midi_output_path)?, &state.take_notes(), SOUND_SAMPLE_RATE, bpm)?;
+    }
 
-    println!("Generated {}", VIDEO_OUTPUT_PATH);
-    println!("Generated {}", AUDIO_OUTPUT_PATH);
+    if let (Some(spectrogram), Some(path)) = (&spectrogram, &options.spectrogram_output_path) {
+        spectrogram.write(&mut create_sink(path)?, options.fps)?;
+    }
+    if let (Some(waveform), Some(path)) = (&waveform, &options.waveform_output_path) {
+        waveform.write(&mut create_sink(path)?, options.fps)?;
+    }
+
+    if streams.has_video() {
+        report_generated(&options.video_output_path);
+    }
+    if streams.has_audio() {
+        report_generated(&options.audio_output_path);
+    }
+    let optional_outputs = [&options.midi_output_path, &options.spectrogram_output_path, &options.waveform_output_path];
+    for path in optional_outputs.into_iter().flatten() {
+        report_generated(path);
+    }
     Ok(())
 }
@@ -0,0 +1,235 @@
+//! Sample rate conversion with a polyphase windowed-sinc filter
+//!
+//! https://ccrma.stanford.edu/~jos/resample/
+use std::str::FromStr;
+use crate::rational::Rational;
+
+/// Bounds the filter table to this many phases times the taps, 1 MB at the
+/// high quality. The common rates reduce to far fewer phases (44100 to 48000
+/// is 160/147, so 40 KB). Rates that reduce to more, like 44100 to 47999,
+/// use the nearest phase before the exact one, which costs some of the
+/// stopband attenuation.
+const MAX_PHASES: usize = 4096;
+
+/// Trade-off between the quality of the conversion and its cost. The longer
+/// filters also hold back more input before the output comes out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    /// Short filter, some aliasing near the Nyquist frequency
+    Low,
+    #[default]
+    Medium,
+    /// Long filter with a steep transition band and about 100 dB of stopband attenuation
+    High,
+}
+
+impl Quality {
+    /// Input samples under the filter, its Kaiser window beta and the share
+    /// of the Nyquist band it passes
+    fn filter(self) -> (usize, f64, f64) {
+        match self {
+            Quality::Low => (8, 5.0, 0.80),
+            Quality::Medium => (32, 8.0, 0.90),
+            Quality::High => (64, 10.0, 0.95),
+        }
+    }
+}
+
+impl FromStr for Quality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Quality::Low),
+            "medium" => Ok(Quality::Medium),
+            "high" => Ok(Quality::High),
+            _ => Err(format!("unknown resampling quality {s}, expected low, medium or high")),
+        }
+    }
+}
+
+/// Modified Bessel function of the first kind of order 0
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum*1e-12 {
+        term *= (x/(2.0*k))*(x/(2.0*k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Converts interleaved samples from one rate to another
+///
+/// The output rate over the input rate is reduced to `up/down`. Every output
+/// sample falls at one of the `up` phases between two input samples and each
+/// phase has its own set of filter taps, see [`MAX_PHASES`] for the memory
+/// it takes. The filter is centered on the output sample, so the output is
+/// not delayed against the input, but the input has to run half of the
+/// filter ahead. [`Resampler::finish`] pushes the rest out.
+pub struct Resampler {
+    channels: usize,
+    up: usize,
+    down: usize,
+    taps: usize,
+    /// Phases in the filter table, `up` unless it's above [`MAX_PHASES`]
+    phases: usize,
+    /// `taps` coefficients for each of the `phases`
+    filter: Vec<f32>,
+    /// Interleaved input that is still under the filter
+    input: Vec<f32>,
+    /// Input frame of the next output relative to the start of `input` and its phase
+    index: usize,
+    phase: usize,
+}
+
+impl Resampler {
+    pub fn new(input_rate: usize, output_rate: usize, channels: usize, quality: Quality) -> Self {
+        use std::f64::consts::PI;
+
+        let ratio = Rational::new(output_rate, input_rate).reduce();
+        let (up, down) = (ratio.num, ratio.den);
+        let (taps, beta, passband) = quality.filter();
+        // When going down the cutoff follows the Nyquist frequency of the output
+        let cutoff = passband*(up as f64/down as f64).min(1.0);
+
+        let half = taps as f64/2.0;
+        let phases = up.min(MAX_PHASES);
+        let mut filter = Vec::with_capacity(phases*taps);
+        for phase in 0..phases {
+            let offset = phase as f64/phases as f64;
+            for tap in 0..taps {
+                // Distance from the output sample to the input sample under the tap
+                let t = offset + half - 1.0 - tap as f64;
+                let x = PI*cutoff*t;
+                let sinc = if x == 0.0 {1.0} else {x.sin()/x};
+                let window = (1.0 - (t/half)*(t/half)).max(0.0).sqrt();
+                filter.push((cutoff*sinc*bessel_i0(beta*window)/bessel_i0(beta)) as f32);
+            }
+        }
+
+        Self {
+            channels,
+            up,
+            down,
+            taps,
+            phases,
+            filter,
+            // The samples before the start are silence
+            input: vec![0.0; (taps/2 - 1)*channels],
+            index: 0,
+            phase: 0,
+        }
+    }
+
+    /// Whether the rates are the same and the samples pass through untouched
+    pub fn is_identity(&self) -> bool {
+        self.up == self.down
+    }
+
+    /// Converts `input` and appends what is ready of the output to `output`
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        if self.is_identity() {
+            output.extend_from_slice(input);
+            return;
+        }
+
+        self.input.extend_from_slice(input);
+        let frames = self.input.len()/self.channels;
+        while self.index + self.taps <= frames {
+            let row = self.phase*self.phases/self.up;
+            let taps = &self.filter[row*self.taps..(row + 1)*self.taps];
+            let window = &self.input[self.index*self.channels..(self.index + self.taps)*self.channels];
+            for channel in 0..self.channels {
+                let sample = taps.iter()
+                    .zip(window.iter().skip(channel).step_by(self.channels))
+                    .map(|(tap, x)| tap*x)
+                    .sum();
+                output.push(sample);
+            }
+            self.phase += self.down;
+            self.index += self.phase/self.up;
+            self.phase %= self.up;
+        }
+
+        // Drop the input no output is going to look at anymore
+        let consumed = self.index.min(frames);
+        self.input.drain(..consumed*self.channels);
+        self.index -= consumed;
+    }
+
+    /// Flushes the output that was waiting for the input ahead
+    pub fn finish(&mut self, output: &mut Vec<f32>) {
+        if !self.is_identity() {
+            let silence = vec![0.0; self.taps/2*self.channels];
+            self.process(&silence, output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALITIES: [Quality; 3] = [Quality::Low, Quality::Medium, Quality::High];
+    const RATES: [(usize, usize); 5] = [(44100, 48000), (48000, 44100), (22050, 48000), (96000, 48000), (44100, 47999)];
+
+    /// Resamples stereo `input` in uneven blocks
+    fn resample(input_rate: usize, output_rate: usize, quality: Quality, input: &[f32]) -> Vec<f32> {
+        let mut resampler = Resampler::new(input_rate, output_rate, 2, quality);
+        let mut output = Vec::new();
+        for block in input.chunks(2*777) {
+            resampler.process(block, &mut output);
+        }
+        resampler.finish(&mut output);
+        output
+    }
+
+    #[test]
+    fn output_length_follows_the_ratio() {
+        for quality in QUALITIES {
+            for (input_rate, output_rate) in RATES {
+                let frames = 12345;
+                let output = resample(input_rate, output_rate, quality, &vec![0.0; 2*frames]);
+                assert_eq!(output.len()%2, 0);
+                let expected = frames as f64*output_rate as f64/input_rate as f64;
+                let actual = (output.len()/2) as f64;
+                assert!((actual - expected).abs() <= 1.0, "{input_rate} to {output_rate}: {actual} frames instead of {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn dc_passes_with_unity_gain() {
+        for quality in QUALITIES {
+            for (input_rate, output_rate) in RATES {
+                let output = resample(input_rate, output_rate, quality, &vec![0.5; 2*input_rate/10]);
+                // Away from the edges where the filter sees the silence around the input
+                let middle = &output[output.len()/4..output.len()*3/4];
+                for sample in middle {
+                    assert!((sample - 0.5).abs() < 0.005, "{input_rate} to {output_rate} at {quality:?}: DC is {sample}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn equal_rates_are_identity() {
+        let input: Vec<f32> = (0..2000).map(|i| (i as f32*0.37).sin()).collect();
+        for quality in QUALITIES {
+            let resampler = Resampler::new(44100, 44100, 2, quality);
+            assert!(resampler.is_identity());
+            assert_eq!(resample(44100, 44100, quality, &input), input);
+        }
+    }
+
+    #[test]
+    fn filter_table_is_bounded() {
+        let resampler = Resampler::new(44100, 47999, 2, Quality::High);
+        assert_eq!(resampler.filter.len(), MAX_PHASES*resampler.taps);
+        let resampler = Resampler::new(44100, 48000, 2, Quality::High);
+        assert_eq!(resampler.filter.len(), 160*resampler.taps);
+    }
+}
@@ -0,0 +1,241 @@
+//! RIFF chunks and the Windows multimedia structures shared by the AVI and WAV containers
+//!
+//! https://learn.microsoft.com/en-us/windows/win32/xaudio2/resource-interchange-file-format--riff-
+#![allow(dead_code)]
+#![allow(non_snake_case)]
+#![allow(non_camel_case_types)]
+
+use std::result;
+use std::mem;
+use std::convert::*;
+use std::fmt;
+use std::io;
+use std::slice;
+
+pub fn chop_u32(content: &mut &[u8]) -> u32 {
+    let result = u32::from_le_bytes((*content)[0..4].try_into().unwrap());
+    (*content) = &(*content)[4..];
+    result
+}
+
+pub enum Entry<'a> {
+    Chunk(Chunk<'a>),
+    List(List<'a>),
+}
+
+pub fn chop_entry<'a>(content: &mut &'a [u8]) -> Entry<'a> {
+    let id = chop_u32(content);
+    if id != LIST {
+        let size = chop_u32(content) as usize;
+        let size_padded = pad_size(size, 2);
+        let chunk = Chunk {
+            id: FOURCC::from_u32(id),
+            content: &(*content)[0..size],
+        };
+        (*content) = &(*content)[size_padded..];
+        Entry::Chunk(chunk)
+    } else {
+        let size = chop_u32(content) as usize - size_of::<u32>();
+        let r#type = FOURCC::from_u32(chop_u32(content));
+        let list = List {
+            r#type,
+            content: &(*content)[0..size],
+        };
+        (*content) = &(*content)[size..];
+        Entry::List(list)
+    }
+}
+
+#[derive(Debug)]
+pub struct Chunk<'a> {
+    pub id: FOURCC,
+    pub content: &'a [u8]
+}
+
+pub fn chop_chunk<'a>(content: &mut &'a [u8]) -> Chunk<'a> {
+    match chop_entry(content) {
+        Entry::Chunk(chunk) => chunk,
+        _ => unreachable!("Not chunk"),
+    }
+}
+
+#[derive(Debug)]
+pub struct List<'a> {
+    pub r#type: FOURCC,
+    pub content: &'a [u8],
+}
+
+pub fn chop_list<'a>(content: &mut &'a [u8]) -> List<'a> {
+    match chop_entry(content) {
+        Entry::List(list) => list,
+        _ => unreachable!("Not list"),
+    }
+}
+
+pub const RIFF: u32 = 0x46464952;
+pub const LIST: u32 = 0x5453494c;
+
+pub fn pad_size(size: usize, width: usize) -> usize {
+    (size + width - 1)/width*width
+}
+
+pub type WORD = u16;
+pub type DWORD = u32;
+pub type LONG = i32;
+pub type SHORT = i16;
+
+pub struct FOURCC(pub [u8; 4]);
+
+impl FOURCC {
+    pub fn from_str(s: &str) -> Option<Self> {
+        s.as_bytes().try_into().ok().map(FOURCC)
+    }
+
+    pub const fn from_u32(x: u32) -> Self {
+        Self(x.to_le_bytes())
+    }
+
+    pub const fn to_u32(&self) -> u32 {
+        let Self(bytes) = self;
+        u32::from_le_bytes(*bytes)
+    }
+}
+
+impl fmt::Display for FOURCC {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> result::Result<(), fmt::Error> {
+        let FOURCC(bytes) = self;
+        write!(fmt, "0x{bytes:x} \"{str}\"",
+               bytes = u32::from_le_bytes(*bytes),
+               str = str::from_utf8(bytes).unwrap())
+    }
+}
+
+impl fmt::Debug for FOURCC {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> result::Result<(), fmt::Error> {
+        fmt::Display::fmt(self, fmt)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed(1))]
+pub struct WAVEFORMATEX {
+    pub wFormatTag: WORD,
+    pub nChannels: WORD,
+    pub nSamplesPerSec: DWORD,
+    pub nAvgBytesPerSec: DWORD,
+    pub nBlockAlign: WORD,
+    pub wBitsPerSample: WORD,
+    pub cbSize: WORD,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed(1))]
+pub struct GUID {
+    pub Data1: u32,
+    pub Data2: u16,
+    pub Data3: u16,
+    pub Data4: [u8; 8],
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed(1))]
+pub struct WAVEFORMATEXTENSIBLE {
+    pub Format: WAVEFORMATEX,
+    pub Samples: WORD,//SamplesUnion,
+    pub dwChannelMask: DWORD,
+    pub SubFormat: GUID,
+}
+
+pub const WAVE_FORMAT_PCM: WORD = 0x0001;
+pub const WAVE_FORMAT_IEEE_FLOAT: WORD = 0x0003;
+pub const WAVE_FORMAT_EXTENSIBLE: WORD = 0xFFFE;
+
+/// Last 12 bytes shared by all the `KSDATAFORMAT_SUBTYPE_*` GUIDs. The first
+/// 2 bytes repeat the corresponding `WAVE_FORMAT_*` tag.
+pub const KSDATAFORMAT_SUBTYPE: GUID = GUID {
+    Data1: 0,
+    Data2: 0,
+    Data3: 16,
+    Data4: [128, 0, 0, 170, 0, 56, 155, 113],
+};
+
+pub const SPEAKER_FRONT_LEFT: DWORD = 0x1;
+pub const SPEAKER_FRONT_RIGHT: DWORD = 0x2;
+pub const SPEAKER_FRONT_CENTER: DWORD = 0x4;
+
+/// Format of the 32 bit float samples produced by the synthesizer
+pub fn float_wave_format(sample_rate: usize, channels: usize) -> WAVEFORMATEXTENSIBLE {
+    let block_align = channels*size_of::<f32>();
+    WAVEFORMATEXTENSIBLE {
+        Format: WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_EXTENSIBLE,
+            nChannels: channels as WORD,
+            nSamplesPerSec: sample_rate as DWORD,
+            nAvgBytesPerSec: (sample_rate*block_align) as DWORD,
+            nBlockAlign: block_align as WORD,
+            wBitsPerSample: 32,
+            cbSize: 22,
+        },
+        Samples: 32,
+        dwChannelMask: match channels {
+            1 => SPEAKER_FRONT_CENTER,
+            2 => SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT,
+            _ => 0,
+        },
+        SubFormat: GUID {
+            Data1: WAVE_FORMAT_IEEE_FLOAT as u32,
+            ..KSDATAFORMAT_SUBTYPE
+        },
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub enum SamplesUnion {
+    wValidBitsPerSample(WORD),
+    wSamplesPerBlock(WORD),
+    wReserved(WORD),
+}
+
+pub fn transmute_struct_to_chunk<'a, T>(id: FOURCC, s: &'a T) -> Chunk<'a> {
+    let content = unsafe {
+        slice::from_raw_parts(mem::transmute::<&'a T, *const u8>(s), size_of::<T>())
+    };
+    Chunk {id, content}
+}
+
+pub fn transmute_chunk_to_struct<'a, T>(chunk: &Chunk<'a>) -> &'a T {
+    let expected = size_of::<T>();
+    let actual = chunk.content.len() as usize;
+    assert!(
+        expected <= actual,
+        "Unexpected chunk size. Expected size of structure {}, but got {}",
+        expected, actual
+    );
+    unsafe {
+        mem::transmute::<*const u8, &'a T>(chunk.content.as_ptr())
+    }
+}
+
+pub fn write_list<'a>(sink: &mut impl io::Write, list: &List<'a>) -> io::Result<()> {
+    sink.write_all(&LIST.to_le_bytes())?;
+    let list_size = (list.content.len() + size_of::<FOURCC>()) as u32;
+    sink.write_all(&list_size.to_le_bytes())?;
+    sink.write_all(&list.r#type.to_u32().to_le_bytes())?;
+    sink.write_all(list.content)?;
+    Ok(())
+}
+
+pub fn write_chunk<'a>(sink: &mut impl io::Write, chunk: &Chunk<'a>) -> io::Result<()> {
+    sink.write_all(&chunk.id.to_u32().to_le_bytes())?;
+    let chunk_size = chunk.content.len() as u32;
+    sink.write_all(&chunk_size.to_le_bytes())?;
+    sink.write_all(chunk.content)?;
+
+    if chunk_size%2 != 0 {
+        sink.write_all(&[0])?;
+    }
+
+    Ok(())
+}
+
@@ -0,0 +1,271 @@
+//! Per scene settings of the simulation, shared by the preview and the render
+//!
+//! A scene file consists of `key = value` lines. Empty lines and lines
+//! starting with `#` are ignored. Relative paths are resolved against the
+//! directory of the scene file.
+//!
+//! ```text
+//! # Generations 0-1 play a plucked saw, the rest a soft square
+//! instrument = saw attack=0.001 decay=0.2 sustain=0 release=0
+//! instrument = square attack=0.02 gain=0.3
+//!
+//! # Or instead of the instruments generations 0-1 play the kick, 2-3 the snare, the rest the hat
+//! sample = samples/kick.wav
+//! sample = samples/snare.wav
+//! sample = samples/hat.wav
+//!
+//! # Up to 8 notes at once, new ones cut off the oldest
+//! polyphony = 8
+//! voice_stealing = oldest
+//!
+//! # Every generation one degree higher in D minor, on a swung 16th grid
+//! scale = minor
+//! root = D3
+//! bpm = 120
+//! grid = 4
+//! swing = 0.6
+//!
+//! # The mix goes through these in order, see the effects module
+//! effect = delay time=0.375 feedback=0.35 mix=0.25
+//! effect = reverb room=0.8
+//!
+//! # The waveform of the frame in the top left corner, see the overlay module
+//! overlay = scope x=20 y=20 width=240 height=80 color=80C0FF
+//!
+//! # Big rects play low notes, see the mapping module
+//! map = pitch area min=12 max=-24
+//! ```
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use crate::effects::Effect;
+use crate::mapping::Mapping;
+use crate::overlay::Overlay;
+use crate::synth::{Instrument, Sample, INSTRUMENTS};
+use crate::music::{self, Grid, Scale};
+use crate::wav;
+
+const POLYPHONY: usize = 32;
+/// The note of the first rect
+const ROOT: i32 = -24;
+const GRID_STEPS_PER_BEAT: usize = 4;
+const SWING: f32 = 0.5;
+
+/// Which voice gives way to a new note when all of them are playing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceStealing {
+    #[default]
+    Oldest,
+    Quietest,
+    LowestNote,
+}
+
+impl FromStr for VoiceStealing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oldest" => Ok(VoiceStealing::Oldest),
+            "quietest" => Ok(VoiceStealing::Quietest),
+            "lowest" => Ok(VoiceStealing::LowestNote),
+            _ => Err(format!("unknown voice stealing policy {s}, expected oldest, quietest or lowest")),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Scene {
+    /// Instruments of the rects picked by their generation, see
+    /// [`Scene::instruments`]
+    pub instruments: Vec<Instrument>,
+    /// One-shot samples the rects play instead of the synth instruments,
+    /// picked by the generation of the rect. Recorded at A4, so they are
+    /// pitched by the note of the rect.
+    pub samples: Vec<Arc<Sample>>,
+    /// How many notes can sound at once
+    pub polyphony: usize,
+    pub voice_stealing: VoiceStealing,
+    /// The generations of the rects go up the degrees of the scale. Without
+    /// a scale every generation is a few semitones higher than the previous one.
+    pub scale: Option<Scale>,
+    /// Semitones from A4
+    pub root: i32,
+    /// Tempo of the grid the beeps are delayed to. No grid if not set.
+    pub bpm: Option<f32>,
+    pub grid_steps_per_beat: usize,
+    pub swing: f32,
+    /// Applied to the mix of the beeps in order, before the limiter
+    pub effects: Vec<Effect>,
+    /// Drawn over the rects in order
+    pub overlays: Vec<Overlay>,
+    /// Override the parameters of the beeps that otherwise come from the
+    /// generation of the rect, applied in order
+    pub mappings: Vec<Mapping>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            instruments: Vec::new(),
+            samples: Vec::new(),
+            polyphony: POLYPHONY,
+            voice_stealing: VoiceStealing::default(),
+            scale: None,
+            root: ROOT,
+            bpm: None,
+            grid_steps_per_beat: GRID_STEPS_PER_BEAT,
+            swing: SWING,
+            effects: Vec::new(),
+            overlays: Vec::new(),
+            mappings: Vec::new(),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Scene {
+    /// The instruments of the scene or the built-in ones if it has none
+    pub fn instruments(&self) -> &[Instrument] {
+        if self.instruments.is_empty() {INSTRUMENTS} else {&self.instruments}
+    }
+
+    pub fn grid(&self) -> Option<Grid> {
+        self.bpm.map(|bpm| Grid {
+            bpm,
+            steps_per_beat: self.grid_steps_per_beat,
+            swing: self.swing,
+        })
+    }
+
+    pub fn load(file_path: &str) -> io::Result<Scene> {
+        let content = fs::read_to_string(file_path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{file_path}: {err}")))?;
+        let dir = Path::new(file_path).parent().unwrap_or(Path::new(""));
+        let mut scene = Scene::default();
+        // The root only means something with a scale, which may come later
+        let mut root_line = None;
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fail = |message: String| invalid_data(format!("{file_path}:{}: {message}", index + 1));
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(fail(format!("expected `key = value`, got `{line}`")));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "instrument" => scene.instruments.push(value.parse().map_err(fail)?),
+                "sample" => {
+                    let path = dir.join(value);
+                    let wav = wav::read(&path.to_string_lossy()).map_err(|err| fail(err.to_string()))?;
+                    scene.samples.push(Arc::new(Sample::from_wav(&wav)));
+                }
+                "polyphony" => {
+                    scene.polyphony = value.parse().map_err(|err| fail(format!("invalid polyphony {value}: {err}")))?;
+                    if scene.polyphony == 0 {
+                        return Err(fail("polyphony must be positive".to_string()));
+                    }
+                }
+                "voice_stealing" => scene.voice_stealing = value.parse().map_err(fail)?,
+                "scale" => scene.scale = Some(value.parse().map_err(fail)?),
+                "root" => {
+                    scene.root = music::parse_note(value).map_err(fail)?;
+                    root_line = Some(index + 1);
+                }
+                "bpm" => {
+                    let bpm: f32 = value.parse().map_err(|err| fail(format!("invalid bpm {value}: {err}")))?;
+                    if !bpm.is_finite() || bpm <= 0.0 {
+                        return Err(fail("bpm must be positive".to_string()));
+                    }
+                    scene.bpm = Some(bpm);
+                }
+                "grid" => {
+                    scene.grid_steps_per_beat = value.parse().map_err(|err| fail(format!("invalid grid {value}: {err}")))?;
+                    if scene.grid_steps_per_beat == 0 {
+                        return Err(fail("grid must be positive".to_string()));
+                    }
+                }
+                "swing" => {
+                    scene.swing = value.parse().map_err(|err| fail(format!("invalid swing {value}: {err}")))?;
+                    if !(0.5..1.0).contains(&scene.swing) {
+                        return Err(fail("swing must be at least 0.5 and less than 1.0".to_string()));
+                    }
+                }
+                "effect" => scene.effects.push(value.parse().map_err(fail)?),
+                "overlay" => scene.overlays.push(value.parse().map_err(fail)?),
+                "map" => scene.mappings.push(value.parse().map_err(fail)?),
+                key => return Err(fail(format!("unknown key `{key}`"))),
+            }
+        }
+        if let (Some(line), None) = (root_line, scene.scale) {
+            return Err(invalid_data(format!("{file_path}:{line}: root has no effect without a scale")));
+        }
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads the `content` as a scene file called `name`
+    fn load(name: &str, content: &str) -> io::Result<Scene> {
+        let path = std::env::temp_dir().join(format!("{}-{name}.scene", std::process::id()));
+        fs::write(&path, content).unwrap();
+        let scene = Scene::load(&path.to_string_lossy());
+        fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    fn load_err(name: &str, content: &str) -> io::Error {
+        match load(name, content) {
+            Ok(_) => panic!("{content:?} was accepted"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn root_needs_a_scale() {
+        let err = load_err("root", "# D3 alone\n\nroot = D3\nbpm = 100\n");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with("root.scene:3: root has no effect without a scale"), "{err}");
+        // The scale may come after the root
+        let scene = load("root-scale", "root = D3\nscale = minor\n").unwrap();
+        assert_eq!(scene.root, -19);
+        assert_eq!(scene.scale, Some(Scale::Minor));
+    }
+
+    #[test]
+    fn tempo() {
+        let scene = load("tempo", "bpm = 90\nswing = 0.6\ngrid = 3\n").unwrap();
+        let grid = scene.grid().unwrap();
+        assert_eq!((grid.bpm, grid.steps_per_beat, grid.swing), (90.0, 3, 0.6));
+        // No grid without a tempo
+        let scene = load("no-tempo", "swing = 0.6\n").unwrap();
+        assert!(scene.grid().is_none());
+        assert_eq!(scene.swing, 0.6);
+    }
+
+    #[test]
+    fn invalid_tempo() {
+        for (index, content) in ["bpm = 0", "bpm = -120", "bpm = inf", "bpm = fast", "swing = 0.4", "swing = 1", "grid = 0"].iter().enumerate() {
+            let err = load_err(&format!("invalid-tempo-{index}"), content);
+            assert!(err.to_string().contains(":1: "), "{content}: {err}");
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let scene = load("empty", "# nothing\n\n").unwrap();
+        assert_eq!(scene.root, ROOT);
+        assert!(scene.scale.is_none() && scene.bpm.is_none());
+        assert_eq!(scene.polyphony, POLYPHONY);
+        assert_eq!(scene.instruments().len(), INSTRUMENTS.len());
+    }
+}
@@ -1,5 +1,17 @@
+use crate::audio::Mixer;
+use crate::config::SOUND_CHANNELS;
+use crate::mapping::{Sources, Target};
+use std::sync::Arc;
+use crate::midi::{self, NoteEvent};
+use crate::scene::{Scene, VoiceStealing};
+use crate::synth::{Instrument, Sample, SamplePlayer, Tone, Voice};
+
 const SPLIT_REDUCE_FACTOR: f32 = 0.90;
 const RECT_VEL: f32 = 1000.0;
+/// Direction of the first rect. The splits only flip its signs, so every
+/// rect moves along one of the four diagonals at the same speed.
+const RECT_DX: f32 = 0.7;
+const RECT_DY: f32 = 0.8;
 const RECT_WIDTH: usize = 100;
 const RECT_HEIGHT: usize = 100;
 const RECTS_CAP: usize = 100;
@@ -8,6 +20,14 @@ const RECT_AREA_THRESHOLD: f32 = 1853.0;
     // RECT_WIDTH as f32 * RECT_HEIGHT as f32 * (SPLIT_REDUCE_FACTOR.powf(10.0 * 2.0));
 const BEEP_DURATION: f32 = 0.2;
 const BEEP_VOLUME: f32 = 0.05;
+/// MIDI velocity of the beeps at [`BEEP_VOLUME`]
+const BEEP_VELOCITY: f32 = 100.0;
+/// How fast a stolen voice fades out instead of clicking off
+const STOLEN_VOICE_FADE: f32 = 0.005;
+/// Time constant of the level of the voices going down, see [`Beeper::push`]
+const VOICE_LEVEL_RELEASE: f32 = 0.05;
+/// How many generations of the rects play the same instrument or sample
+const GENERATIONS_PER_INSTRUMENT: u32 = 2;
 
 fn hsl2rgb(h: f32, s: f32, l: f32) -> (f32, f32, f32) {
     let mut r = (((0.0 + h*6.0).rem_euclid(6.0) - 3.0).abs() - 1.0).clamp(0.0, 1.0);
@@ -20,6 +40,7 @@ fn hsl2rgb(h: f32, s: f32, l: f32) -> (f32, f32, f32) {
     (r, g, b)
 }
 
+/// Fills the rectangle with opaque ARGB pixels
 fn fill_gay_rectangle_rba(canvas: &mut [u32], canvas_stride: usize, rect: (i32, i32, u32, u32)) {
     let w = canvas_stride as i32;
     let h = canvas.len() as i32 / w;
@@ -35,6 +56,7 @@ fn fill_gay_rectangle_rba(canvas: &mut [u32], canvas_stride: usize, rect: (i32,
                 let v = y as f32 / h as f32;
                 let (rf, gf, bf) = hsl2rgb((u + v) * 2.0, 1.0, 0.80);
                 canvas[(y as usize)*canvas_stride + x as usize] =
+                    0xFF << (8*3) |
                     ((rf * 255.0) as u32) << (8*2) |
                     ((gf * 255.0) as u32) << (8*1) |
                     ((bf * 255.0) as u32) << (8*0);
@@ -51,6 +73,8 @@ struct Rect {
     w: f32,
     h: f32,
     note: i32,
+    /// How many splits the rect is away from the first one
+    generation: u32,
 }
 
 #[derive(Copy, Clone)]
@@ -59,6 +83,22 @@ enum Orient {
     Horz
 }
 
+#[derive(Copy, Clone)]
+struct Hit {
+    orient: Orient,
+    /// When the rect reached the wall, relative to the start of the step
+    time: f32,
+}
+
+/// Time it takes to get from `pos` to `wall` with velocity `vel`, clamped to the step
+fn hit_time(pos: f32, vel: f32, wall: f32, delta_time: f32) -> f32 {
+    if vel == 0.0 {
+        0.0
+    } else {
+        ((wall - pos)/vel).clamp(0.0, delta_time)
+    }
+}
+
 impl Rect {
     fn hitbox(&self) -> (i32, i32, u32, u32) {
         (self.x as i32, self.y as i32, self.w as u32, self.h as u32)
@@ -68,6 +108,46 @@ impl Rect {
         self.w * self.h
     }
 
+    /// Index into a table of `count` sounds for the generation of the rect
+    fn sound_index(&self, count: usize) -> usize {
+        ((self.generation/GENERATIONS_PER_INSTRUMENT) as usize).min(count - 1)
+    }
+
+    /// Properties of the rect at the `hit` for the mappings of the scene
+    fn sources(&self, hit: Hit, width: f32, height: f32) -> Sources {
+        let first_area = RECT_WIDTH as f32 * RECT_HEIGHT as f32;
+        let x = self.x + self.dx * RECT_VEL * hit.time + self.w/2.0;
+        let y = self.y + self.dy * RECT_VEL * hit.time + self.h/2.0;
+        let towards_wall = match hit.orient {
+            Orient::Horz => self.dx,
+            Orient::Vert => self.dy,
+        };
+        // The component towards the wall takes only these two values, so the
+        // velocity flags the orientation of the wall
+        let (slow, fast) = (RECT_DX.abs().min(RECT_DY.abs()), RECT_DX.abs().max(RECT_DY.abs()));
+        Sources {
+            area: (self.area()/RECT_AREA_THRESHOLD).ln()/(first_area/RECT_AREA_THRESHOLD).ln(),
+            size: (self.area()/first_area).sqrt(),
+            // The same hue fill_gay_rectangle_rba draws there
+            hue: ((x/width + y/height)*2.0).rem_euclid(1.0),
+            velocity: if fast > slow {(towards_wall.abs() - slow)/(fast - slow)} else {1.0},
+        }
+    }
+
+    fn instrument<'a>(&self, scene: &'a Scene) -> &'a Instrument {
+        let instruments = scene.instruments();
+        &instruments[self.sound_index(instruments.len())]
+    }
+
+    /// The sample of the scene the rect plays, if the scene has any
+    fn sample<'a>(&self, scene: &'a Scene) -> Option<&'a Arc<Sample>> {
+        if scene.samples.is_empty() {
+            None
+        } else {
+            scene.samples.get(self.sound_index(scene.samples.len()))
+        }
+    }
+
     #[allow(dead_code)]
     fn bounce(self, orient: Orient) -> Rect {
         use self::Orient::*;
@@ -81,6 +161,7 @@ impl Rect {
                     w: self.w,
                     h: self.h,
                     note: self.note,
+                    generation: self.generation,
                 };
                 left
             },
@@ -93,6 +174,7 @@ impl Rect {
                     w: self.w,
                     h: self.h,
                     note: self.note,
+                    generation: self.generation,
                 };
                 left
             },
@@ -111,6 +193,7 @@ impl Rect {
                     w: self.w * SPLIT_REDUCE_FACTOR,
                     h: self.h * SPLIT_REDUCE_FACTOR,
                     note: self.note + RECT_NOTE_STEP,
+                    generation: self.generation + 1,
                 };
                 let right =  Rect {
                     x: self.x,
@@ -120,6 +203,7 @@ impl Rect {
                     w: self.w * SPLIT_REDUCE_FACTOR,
                     h: self.h * SPLIT_REDUCE_FACTOR,
                     note: self.note + RECT_NOTE_STEP,
+                    generation: self.generation + 1,
                 };
                 (left, right)
             },
@@ -132,6 +216,7 @@ impl Rect {
                     w: self.w * SPLIT_REDUCE_FACTOR,
                     h: self.h * SPLIT_REDUCE_FACTOR,
                     note: self.note + RECT_NOTE_STEP,
+                    generation: self.generation + 1,
                 };
                 let right =  Rect {
                     x: self.x,
@@ -141,22 +226,31 @@ impl Rect {
                     w: self.w * SPLIT_REDUCE_FACTOR,
                     h: self.h * SPLIT_REDUCE_FACTOR,
                     note: self.note + RECT_NOTE_STEP,
+                    generation: self.generation + 1,
                 };
                 (left, right)
             },
         }
     }
 
-    fn update(&mut self, delta_time: f32, width: f32, height: f32) -> Option<Orient> {
+    fn update(&mut self, delta_time: f32, width: f32, height: f32) -> Option<Hit> {
         let nx = self.x + self.dx * RECT_VEL * delta_time;
         let ny = self.y + self.dy * RECT_VEL * delta_time;
 
         if nx + self.w as f32 >= width as f32 || nx <= 0.0 {
-            return Some(Orient::Horz);
+            let wall = if nx <= 0.0 {0.0} else {width - self.w};
+            return Some(Hit {
+                orient: Orient::Horz,
+                time: hit_time(self.x, self.dx * RECT_VEL, wall, delta_time),
+            });
         }
 
         if ny + self.h as f32 >= height as f32 || ny <= 0.0 {
-            return Some(Orient::Vert);
+            let wall = if ny <= 0.0 {0.0} else {height - self.h};
+            return Some(Hit {
+                orient: Orient::Vert,
+                time: hit_time(self.y, self.dy * RECT_VEL, wall, delta_time),
+            });
         }
 
         self.x = nx;
@@ -167,72 +261,196 @@ impl Rect {
 }
 
 struct Beep {
+    voice: Voice,
+    /// Semitones from A4
+    note: i32,
     freq: f32,
-    duration: f32,
+    velocity: u8,
+    /// Stereo position from -1.0 (left) to 1.0 (right)
+    pan: f32,
+    /// Time left until the beep starts
+    delay: f32,
+    /// Recent peak of the output, for stealing the quietest voice
+    level: f32,
+    /// Gain of the fade out once the voice is stolen
+    fade: Option<f32>,
+    /// Whether the note on and off were recorded
+    started: bool,
+    released: bool,
+}
+
+impl Beep {
+    fn new(voice: Voice, note: i32, gain: f32, pan: f32, delay: f32) -> Self {
+        Self {
+            voice,
+            note,
+            freq: freq_of_note(note),
+            velocity: (gain/BEEP_VOLUME*BEEP_VELOCITY).round().clamp(1.0, 127.0) as u8,
+            pan,
+            delay,
+            level: 0.0,
+            fade: None,
+            started: false,
+            released: false,
+        }
+    }
+
+    fn note_event(&self, frame: u64, on: bool) -> NoteEvent {
+        NoteEvent {
+            frame,
+            key: midi::key_of_note(self.note),
+            velocity: self.velocity,
+            on,
+        }
+    }
+
+    /// Constant-power panning gains of the left and right channels
+    fn pan_gains(&self) -> (f32, f32) {
+        use std::f32::consts::FRAC_PI_4;
+        let angle = (self.pan.clamp(-1.0, 1.0) + 1.0)*FRAC_PI_4;
+        (angle.cos(), angle.sin())
+    }
+
+    fn is_finished(&self) -> bool {
+        self.voice.is_finished() || self.fade.is_some_and(|fade| fade <= 0.0)
+    }
+
+    /// How much the voice would be missed if stolen. The beeps that didn't
+    /// start yet haven't been heard, so they are never the quietest.
+    fn loudness(&self) -> f32 {
+        if self.delay > 0.0 {f32::INFINITY} else {self.level}
+    }
+
+    /// Fades the voice out. If it didn't start yet it is dropped right away,
+    /// and since it was never heard it doesn't make it to the recorded notes.
+    fn steal(&mut self) {
+        self.fade = Some(if self.delay > 0.0 {0.0} else {1.0});
+    }
 }
 
+/// Synthesizes the beeps into a dry mix. Each voice is scaled by its own gain,
+/// the level of the sum is taken care of by the [`Mixer`].
 struct Beeper {
+    /// In the order the beeps were scheduled
     beeps: Vec<Beep>,
-    time: f32,
-    g: f32,
+    /// Seeds the noise of the voices, so the render is the same every time
+    voice_count: u32,
+    polyphony: usize,
+    voice_stealing: VoiceStealing,
+    /// Frames mixed since the start
+    frame: u64,
+    /// Note on and off of every beep that made it to the mix, if recording
+    notes: Option<Vec<NoteEvent>>,
 }
 
 impl Beeper {
-    fn new() -> Self {
+    fn new(polyphony: usize, voice_stealing: VoiceStealing) -> Self {
         Self {
             beeps: Vec::new(),
-            time: 0.0,
-            g: f32::MIN,
+            voice_count: 0,
+            polyphony,
+            voice_stealing,
+            frame: 0,
+            notes: None,
         }
     }
 
-    fn beep(&mut self, freq: f32, duration: f32) {
-        self.beeps.push(Beep{freq, duration})
+    /// Schedules a `note` of `instrument` held for `duration` seconds and starting
+    /// `delay` seconds after the start of the next [`Beeper::update`]
+    fn beep(&mut self, instrument: &Instrument, note: i32, duration: f32, gain: f32, pan: f32, delay: f32) {
+        let voice = Voice::Tone(Tone::new(instrument, freq_of_note(note), gain, duration, self.voice_count));
+        self.voice_count = self.voice_count.wrapping_add(1);
+        self.push(Beep::new(voice, note, gain, pan, delay));
     }
 
-    fn update(&mut self, samples: &mut [f32], sample_rate: usize) {
-        use std::f32::consts::PI;
+    /// Schedules a one-shot `sample` pitched to `note`, see [`Beeper::beep`]
+    fn play_sample(&mut self, sample: &Arc<Sample>, note: i32, gain: f32, pan: f32, delay: f32) {
+        let voice = Voice::Sample(SamplePlayer::new(Arc::clone(sample), freq_of_note(note), gain));
+        self.push(Beep::new(voice, note, gain, pan, delay));
+    }
 
-        // TODO: sound mixing is an absolute garbage
-        // I really hope to fix it one day. Not on the stream of course.
+    /// Adds the beep stealing a voice if all of them are taken. The voices
+    /// that are fading out after being stolen don't count.
+    fn push(&mut self, beep: Beep) {
+        let active = self.beeps.iter().filter(|beep| beep.fade.is_none()).count();
+        if active >= self.polyphony {
+            let mut candidates = self.beeps.iter_mut().filter(|beep| beep.fade.is_none());
+            let victim = match self.voice_stealing {
+                VoiceStealing::Oldest => candidates.next(),
+                VoiceStealing::Quietest => candidates.min_by(|a, b| a.loudness().total_cmp(&b.loudness())),
+                VoiceStealing::LowestNote => candidates.min_by(|a, b| a.freq.total_cmp(&b.freq)),
+            };
+            if let Some(victim) = victim {
+                victim.steal();
+            }
+        }
+        self.beeps.push(beep);
+    }
+
+    /// Mixes the beeps into interleaved stereo `samples`
+    fn update(&mut self, samples: &mut [f32], sample_rate: usize) {
         let sample_step = 1.0 / sample_rate as f32;
-        for sample in samples.iter_mut() {
-            *sample = 0.0;
+        let level_decay = (-sample_step/VOICE_LEVEL_RELEASE).exp();
+        for frame in samples.chunks_exact_mut(SOUND_CHANNELS) {
+            let [left, right] = frame else { unreachable!("the sound is stereo") };
+            *left = 0.0;
+            *right = 0.0;
             for beep in self.beeps.iter_mut() {
-                if beep.duration > 0.0 {
-                    let p = beep.duration / BEEP_DURATION;
-                    let fader = if p >= 0.95 {
-                        1.0 - (p - 0.95)/0.05
-                    } else if p <= 0.05 {
-                        p/0.05
-                    } else {
-                        1.0
-                    };
-
-                    *sample += (2.0 * PI * beep.freq * self.time).sin() * BEEP_VOLUME * fader;
-                    beep.duration -= sample_step;
+                if beep.delay > 0.0 {
+                    beep.delay -= sample_step;
+                } else if !beep.is_finished() {
+                    if let Some(notes) = self.notes.as_mut() {
+                        if !beep.started {
+                            beep.started = true;
+                            notes.push(beep.note_event(self.frame, true));
+                        }
+                        if !beep.released && (beep.fade.is_some() || !beep.voice.is_held()) {
+                            beep.released = true;
+                            notes.push(beep.note_event(self.frame, false));
+                        }
+                    }
+                    let mut sample = beep.voice.next(sample_rate);
+                    if let Some(fade) = beep.fade.as_mut() {
+                        sample *= *fade;
+                        *fade -= sample_step/STOLEN_VOICE_FADE;
+                    }
+                    beep.level = sample.abs().max(beep.level*level_decay);
+                    let (left_gain, right_gain) = beep.pan_gains();
+                    *left += sample * left_gain;
+                    *right += sample * right_gain;
                 }
             }
+            self.frame += 1;
+        }
 
-            self.g = sample.abs().max(self.g);
-
-            if self.g > 1.0 {
-                *sample /= self.g * self.g * self.g;
+        // The voices that end within this frame are released at the next one
+        if let Some(notes) = self.notes.as_mut() {
+            for beep in self.beeps.iter_mut().filter(|beep| beep.started && !beep.released && beep.is_finished()) {
+                beep.released = true;
+                notes.push(beep.note_event(self.frame, false));
             }
-
-            self.time += sample_step;
         }
 
-        self.beeps.retain(|beep| beep.duration > 0.0);
+        self.beeps.retain(|beep| !beep.is_finished());
     }
 }
 
 pub struct State {
     rects: Vec<Rect>,
-    to_split: Vec<(usize, Orient)>,
+    to_split: Vec<(usize, Hit)>,
     width: f32,
     height: f32,
     beeper: Beeper,
+    scene: Scene,
+    /// Time since the start of the simulation. Kept in f64 so the grid of
+    /// the beeps doesn't drift in a long preview.
+    time: f64,
+    /// Created on the first [`State::sound`] call when the sample rate is known
+    mixer: Option<Mixer>,
+    /// Extra gain of the mixer, see [`State::set_gain`]
+    gain: f32,
+    /// No beeps are scheduled, see [`State::mute`]
+    muted: bool,
 }
 
 fn freq_of_note(note: i32) -> f32 {
@@ -240,20 +458,26 @@ fn freq_of_note(note: i32) -> f32 {
 }
 
 impl State {
-    pub fn new(width: f32, height: f32) -> Self {
+    pub fn new(width: f32, height: f32, scene: &Scene) -> Self {
         let mut rects = Vec::new();
         rects.push(Rect {
             x: 30.0, y: 100.0,
-            dx: 0.7, dy: 0.8,
+            dx: RECT_DX, dy: RECT_DY,
             w: RECT_WIDTH as f32, h: RECT_HEIGHT as f32,
             note: -24,
+            generation: 0,
         });
         Self {
             rects,
             to_split: Vec::new(),
             width,
             height,
-            beeper: Beeper::new()
+            beeper: Beeper::new(scene.polyphony, scene.voice_stealing),
+            scene: scene.clone(),
+            time: 0.0,
+            mixer: None,
+            gain: 1.0,
+            muted: false,
         }
     }
 
@@ -263,23 +487,90 @@ impl State {
         }
     }
 
+    /// Sets the gain of the mix before the limiter. Takes effect only before
+    /// the first [`State::sound`] call.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    /// Stops scheduling the beeps. Only [`State::sound`] mixes them away,
+    /// so a render that never calls it would pile them up.
+    pub fn mute(&mut self) {
+        self.muted = true;
+    }
+
+    /// Starts recording the notes of the beeps for [`State::take_notes`].
+    /// The beeps that lost their voice while waiting for the grid are left
+    /// out, they are not in the sound either.
+    pub fn record_notes(&mut self) {
+        self.beeper.notes.get_or_insert_with(Vec::new);
+    }
+
+    /// Note on and off events recorded so far, timed in frames of the sound
+    pub fn take_notes(&mut self) -> Vec<NoteEvent> {
+        self.beeper.notes.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Generates the next interleaved stereo samples
     pub fn sound(&mut self, sample: &mut [f32], sample_rate: usize) {
         self.beeper.update(sample, sample_rate);
+        self.mixer
+            .get_or_insert_with(|| Mixer::new(sample_rate, SOUND_CHANNELS, self.gain, &self.scene.effects))
+            .process(sample);
     }
 
     pub fn update(&mut self, delta_time: f32) {
         for (index, rect) in self.rects.iter_mut().enumerate() {
-            if let Some(orient) = rect.update(delta_time, self.width, self.height) {
-                self.to_split.push((index, orient));
+            if let Some(hit) = rect.update(delta_time, self.width, self.height) {
+                self.to_split.push((index, hit));
             }
         }
 
-        for (index, orient) in self.to_split.iter().rev() {
+        for (index, hit) in self.to_split.iter().rev() {
             let rect = self.rects.remove(*index);
 
-            self.beeper.beep(freq_of_note(rect.note), BEEP_DURATION);
+            // The beep comes from where the rect hit the wall. The sound of the
+            // step has been generated already, so the beep is delayed by one step,
+            // but within the next one it starts at the exact sample of the hit.
+            let hit_x = rect.x + rect.dx * RECT_VEL * hit.time;
+            let pan = (hit_x + rect.w/2.0)/self.width*2.0 - 1.0;
+            let mut note = match self.scene.scale {
+                Some(scale) => scale.note(self.scene.root, rect.generation as i32),
+                None => rect.note,
+            };
+            let mut instrument = rect.instrument(&self.scene);
+            let mut sample = rect.sample(&self.scene);
+            let mut gain = BEEP_VOLUME;
+            let mut duration = BEEP_DURATION;
+            let sources = rect.sources(*hit, self.width, self.height);
+            for mapping in self.scene.mappings.iter() {
+                let value = mapping.value(&sources);
+                match mapping.target {
+                    Target::Pitch => note = match self.scene.scale {
+                        Some(scale) => scale.note(self.scene.root, value.round() as i32),
+                        None => value.round() as i32,
+                    },
+                    Target::Timbre => {
+                        let index = value.round().max(0.0) as usize;
+                        let instruments = self.scene.instruments();
+                        instrument = &instruments[index.min(instruments.len() - 1)];
+                        sample = self.scene.samples.get(index.min(self.scene.samples.len().saturating_sub(1)));
+                    }
+                    Target::Volume => gain = BEEP_VOLUME*value,
+                    Target::Duration => duration = value,
+                }
+            }
+            let delay = match self.scene.grid() {
+                Some(grid) => (grid.quantize(self.time + hit.time as f64) - self.time) as f32,
+                None => hit.time,
+            };
+            match sample {
+                _ if self.muted => {}
+                Some(sample) => self.beeper.play_sample(sample, note, gain, pan, delay),
+                None => self.beeper.beep(instrument, note, duration, gain, pan, delay),
+            }
 
-            let (left, right) = rect.split(*orient);
+            let (left, right) = rect.split(hit.orient);
             if self.rects.len() < RECTS_CAP && left.area() >= RECT_AREA_THRESHOLD {
                 self.rects.push(left);
             }
@@ -288,7 +579,114 @@ impl State {
             }
         }
         self.to_split.clear();
+        self.time += delta_time as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::INSTRUMENTS;
+
+    const WIDTH: f32 = 800.0;
+    const HEIGHT: f32 = 600.0;
+
+    fn rect(dx: f32, dy: f32, generation: u32) -> Rect {
+        let scale = SPLIT_REDUCE_FACTOR.powi(generation as i32);
+        Rect {
+            x: 100.0, y: 100.0,
+            dx, dy,
+            w: RECT_WIDTH as f32*scale, h: RECT_HEIGHT as f32*scale,
+            note: 0,
+            generation,
+        }
+    }
+
+    const SAMPLE_RATE: usize = 48000;
+    /// Held sine at full volume
+    const HELD: Instrument = INSTRUMENTS[0];
+
+    /// Plays `frames` stereo frames of the beeper, returns the left channel
+    fn play(beeper: &mut Beeper, frames: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; frames*SOUND_CHANNELS];
+        beeper.update(&mut samples, SAMPLE_RATE);
+        samples.iter().step_by(SOUND_CHANNELS).copied().collect()
+    }
+
+    /// Notes of the beeps that are fading out after being stolen
+    fn stolen(beeper: &Beeper) -> Vec<i32> {
+        beeper.beeps.iter().filter(|beep| beep.fade.is_some()).map(|beep| beep.note).collect()
+    }
+
+    #[test]
+    fn polyphony_limit() {
+        let mut beeper = Beeper::new(3, VoiceStealing::Oldest);
+        for note in 0..10 {
+            beeper.beep(&HELD, note, 10.0, 0.1, 0.0, 0.0);
+            play(&mut beeper, 100);
+            let active = beeper.beeps.iter().filter(|beep| beep.fade.is_none()).count();
+            assert_eq!(active, (note as usize + 1).min(3));
+        }
+        // The stolen ones are gone once faded out
+        play(&mut beeper, SAMPLE_RATE/10);
+        assert_eq!(beeper.beeps.iter().map(|beep| beep.note).collect::<Vec<_>>(), [7, 8, 9]);
+    }
+
+    #[test]
+    fn voice_stealing_policies() {
+        // The oldest one is the highest, the second one the lowest and the
+        // third one the quietest
+        for (voice_stealing, victim) in [(VoiceStealing::Oldest, 12), (VoiceStealing::LowestNote, -12), (VoiceStealing::Quietest, 5)] {
+            let mut beeper = Beeper::new(3, voice_stealing);
+            for (note, gain) in [(12, 0.1), (-12, 0.1), (5, 0.01)] {
+                beeper.beep(&HELD, note, 10.0, gain, 0.0, 0.0);
+            }
+            play(&mut beeper, SAMPLE_RATE/10);
+            assert!(stolen(&beeper).is_empty());
+            beeper.beep(&HELD, 0, 10.0, 0.1, 0.0, 0.0);
+            assert_eq!(stolen(&beeper), [victim], "{voice_stealing:?}");
+        }
+    }
+
+    #[test]
+    fn stolen_voice_fades_out() {
+        let mut beeper = Beeper::new(1, VoiceStealing::Oldest);
+        beeper.beep(&HELD, 0, 10.0, 1.0, 0.0, 0.0);
+        let before = play(&mut beeper, SAMPLE_RATE/10);
+        let peak = before.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+        // Steals the voice, but starts only after a second
+        beeper.beep(&HELD, 0, 10.0, 1.0, 0.0, 1.0);
+        let fade = (STOLEN_VOICE_FADE*SAMPLE_RATE as f32).round() as usize;
+        let after = play(&mut beeper, 2*fade);
+        // No click, it goes down linearly to silence
+        assert!(after[0].abs() > 0.0 && (after[0] - before[before.len() - 1]).abs() < 0.1*peak);
+        for (n, sample) in after.iter().enumerate() {
+            let bound = peak*(1.0 - n as f32/fade as f32).max(0.0) + 1e-6;
+            assert!(sample.abs() <= bound, "sample {n} of the fade is {sample}, more than {bound}");
+        }
+        assert!(after[fade + 1..].iter().all(|sample| *sample == 0.0));
+        assert_eq!(beeper.beeps.len(), 1);
+    }
+
+    #[test]
+    fn velocity_flags_the_wall() {
+        for (dx, dy) in [(RECT_DX, RECT_DY), (-RECT_DX, RECT_DY), (RECT_DX, -RECT_DY), (-RECT_DX, -RECT_DY)] {
+            for generation in [0, 5, 10] {
+                let rect = rect(dx, dy, generation);
+                let side = rect.sources(Hit {orient: Orient::Horz, time: 0.0}, WIDTH, HEIGHT);
+                let top = rect.sources(Hit {orient: Orient::Vert, time: 0.0}, WIDTH, HEIGHT);
+                assert_eq!((side.velocity, top.velocity), (0.0, 1.0), "{dx} {dy} {generation}");
+            }
+        }
+    }
 
-        // TODO: stereo sound depending on the location of the collision
+    #[test]
+    fn sources_of_the_generations() {
+        let first = rect(RECT_DX, RECT_DY, 0).sources(Hit {orient: Orient::Horz, time: 0.0}, WIDTH, HEIGHT);
+        assert_eq!((first.area, first.size), (1.0, 1.0));
+        // The smallest rect that still splits
+        let last = rect(RECT_DX, RECT_DY, 8).sources(Hit {orient: Orient::Horz, time: 0.0}, WIDTH, HEIGHT);
+        assert!(last.area.abs() < 0.01, "area {}", last.area);
+        assert!((last.size - SPLIT_REDUCE_FACTOR.powi(8)).abs() < 1e-6, "size {}", last.size);
     }
 }
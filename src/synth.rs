@@ -0,0 +1,637 @@
+//! Synthesizer voices: band-limited oscillators shaped by ADSR envelopes
+//! and one-shot samples played back at the pitch of the note
+use std::str::FromStr;
+use std::sync::Arc;
+use crate::audio::Biquad;
+
+/// Pitch the one-shot samples are assumed to be recorded at (A4)
+const SAMPLE_ROOT_FREQ: f32 = 440.0;
+/// Cutoff of the anti-aliasing filter of the samples pitched up, relative to
+/// the Nyquist frequency of the output
+const ANTI_ALIAS_CUTOFF: f64 = 0.45;
+/// Q of the two stages of a 4th order Butterworth filter
+const ANTI_ALIAS_Q: [f64; 2] = [0.5412, 1.3066];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Saw,
+    Triangle,
+    Noise,
+}
+
+/// Envelope times are in seconds, `sustain` is a level from 0.0 to 1.0
+#[derive(Debug, Clone, Copy)]
+pub struct Adsr {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sine" => Ok(Waveform::Sine),
+            "square" => Ok(Waveform::Square),
+            "saw" => Ok(Waveform::Saw),
+            "triangle" => Ok(Waveform::Triangle),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("unknown waveform {s}, expected sine, triangle, square, saw or noise")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Instrument {
+    pub waveform: Waveform,
+    pub envelope: Adsr,
+    pub gain: f32,
+}
+
+/// Instruments of the rects by their generation unless the scene lists its
+/// own. The first big rects play soft tones, the small ones that split off
+/// later sound brighter until the last ones only hiss. The rects past the end
+/// of the table keep the last instrument.
+pub const INSTRUMENTS: &[Instrument] = &[
+    Instrument {
+        waveform: Waveform::Sine,
+        envelope: Adsr {attack: 0.01, decay: 0.0, sustain: 1.0, release: 0.01},
+        gain: 1.0,
+    },
+    Instrument {
+        waveform: Waveform::Triangle,
+        envelope: Adsr {attack: 0.005, decay: 0.05, sustain: 0.8, release: 0.05},
+        gain: 1.0,
+    },
+    Instrument {
+        waveform: Waveform::Square,
+        envelope: Adsr {attack: 0.005, decay: 0.08, sustain: 0.5, release: 0.08},
+        gain: 0.5,
+    },
+    Instrument {
+        waveform: Waveform::Saw,
+        envelope: Adsr {attack: 0.002, decay: 0.1, sustain: 0.3, release: 0.1},
+        gain: 0.5,
+    },
+    Instrument {
+        waveform: Waveform::Noise,
+        envelope: Adsr {attack: 0.001, decay: 0.05, sustain: 0.0, release: 0.0},
+        gain: 0.5,
+    },
+];
+
+impl FromStr for Instrument {
+    type Err = String;
+
+    /// Parses the waveform followed by `key=value` parameters. The ones that
+    /// are not given are taken from the built-in instrument of the waveform.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().ok_or("no waveform provided")?;
+        let waveform: Waveform = name.parse()?;
+        let mut instrument = *INSTRUMENTS.iter()
+            .find(|instrument| instrument.waveform == waveform)
+            .expect("every waveform has a built-in instrument");
+        for word in words {
+            let Some((key, value)) = word.split_once('=') else {
+                return Err(format!("expected `key=value` parameter of {name}, got `{word}`"));
+            };
+            let param = match key {
+                "attack" => &mut instrument.envelope.attack,
+                "decay" => &mut instrument.envelope.decay,
+                "sustain" => &mut instrument.envelope.sustain,
+                "release" => &mut instrument.envelope.release,
+                "gain" => &mut instrument.gain,
+                _ => return Err(format!("unknown parameter {key} of {name}")),
+            };
+            *param = value.parse().map_err(|err| format!("invalid value of {key}: {err}"))?;
+            if !param.is_finite() || *param < 0.0 {
+                return Err(format!("{key} of {name} can't be negative"));
+            }
+        }
+        if instrument.envelope.sustain > 1.0 {
+            return Err(format!("sustain of {name} can't be above 1"));
+        }
+        Ok(instrument)
+    }
+}
+
+/// Correction of a unit step at the phase wrap, spread over the two samples
+/// around it. Subtracting it from a naive waveform removes most of the aliasing.
+///
+/// https://www.martin-finke.de/articles/audio-plugins-018-polyblep-oscillator/
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t/dt;
+        t + t - t*t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0)/dt;
+        t*t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+pub struct Oscillator {
+    waveform: Waveform,
+    freq: f32,
+    /// Position within the period from 0.0 to 1.0
+    phase: f32,
+    /// State of the integrator that turns the square into the triangle.
+    /// Starts once the step of the phase is known.
+    triangle: Option<f32>,
+    /// State of the xorshift generator of the noise
+    noise: u32,
+}
+
+impl Oscillator {
+    /// `seed` makes the noise of different voices uncorrelated while keeping it deterministic
+    pub fn new(waveform: Waveform, freq: f32, seed: u32) -> Self {
+        Self {
+            waveform,
+            freq,
+            phase: 0.0,
+            triangle: None,
+            noise: seed.wrapping_mul(0x9E3779B9) | 1,
+        }
+    }
+
+    fn square(&self, dt: f32) -> f32 {
+        let naive = if self.phase < 0.5 {1.0} else {-1.0};
+        naive + poly_blep(self.phase, dt) - poly_blep((self.phase + 0.5).fract(), dt)
+    }
+
+    pub fn next(&mut self, sample_rate: usize) -> f32 {
+        use std::f32::consts::PI;
+
+        let dt = (self.freq/sample_rate as f32).min(0.5);
+        let sample = match self.waveform {
+            Waveform::Sine => (2.0*PI*self.phase).sin(),
+            Waveform::Square => self.square(dt),
+            Waveform::Saw => 2.0*self.phase - 1.0 - poly_blep(self.phase, dt),
+            Waveform::Triangle => {
+                // Integrating the band-limited square climbs by 2 over the half of the period.
+                // The start is lifted by the part of the step that polyBLEP smears
+                // before the phase 0, otherwise the triangle gets a DC offset.
+                let square = self.square(dt);
+                let triangle = self.triangle.get_or_insert(-1.0 + 2.0*dt);
+                *triangle += 4.0*dt*square;
+                *triangle
+            }
+            Waveform::Noise => {
+                self.noise ^= self.noise << 13;
+                self.noise ^= self.noise >> 17;
+                self.noise ^= self.noise << 5;
+                self.noise as f32/u32::MAX as f32*2.0 - 1.0
+            }
+        };
+        self.phase = (self.phase + dt).fract();
+        sample
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Finished,
+}
+
+/// Linear ADSR envelope
+pub struct Envelope {
+    adsr: Adsr,
+    stage: Stage,
+    level: f32,
+    /// How fast the level goes down in the release stage, per second
+    release_rate: f32,
+}
+
+impl Envelope {
+    pub fn new(adsr: Adsr) -> Self {
+        Self {
+            adsr,
+            stage: Stage::Attack,
+            level: 0.0,
+            release_rate: 0.0,
+        }
+    }
+
+    /// Note off. The release starts from whatever level the envelope is at.
+    pub fn release(&mut self) {
+        if self.stage != Stage::Finished && self.stage != Stage::Release {
+            self.stage = Stage::Release;
+            self.release_rate = self.level/self.adsr.release.max(f32::EPSILON);
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.stage == Stage::Finished
+    }
+
+    pub fn next(&mut self, sample_step: f32) -> f32 {
+        match self.stage {
+            Stage::Attack => {
+                self.level += sample_step/self.adsr.attack.max(f32::EPSILON);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= (1.0 - self.adsr.sustain)*sample_step/self.adsr.decay.max(f32::EPSILON);
+                if self.level <= self.adsr.sustain {
+                    self.level = self.adsr.sustain;
+                    // Percussive envelopes are done without waiting for the note off
+                    self.stage = if self.level > 0.0 {Stage::Sustain} else {Stage::Finished};
+                }
+            }
+            Stage::Sustain => {}
+            Stage::Release => {
+                self.level -= self.release_rate*sample_step;
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Stage::Finished;
+                }
+            }
+            Stage::Finished => {}
+        }
+        self.level
+    }
+}
+
+/// A single note of an [`Instrument`]
+pub struct Tone {
+    oscillator: Oscillator,
+    envelope: Envelope,
+    gain: f32,
+    /// Time left until the note off
+    gate: f32,
+}
+
+impl Tone {
+    /// The note is held for `duration` seconds and then released
+    pub fn new(instrument: &Instrument, freq: f32, gain: f32, duration: f32, seed: u32) -> Self {
+        Self {
+            oscillator: Oscillator::new(instrument.waveform, freq, seed),
+            envelope: Envelope::new(instrument.envelope),
+            gain: instrument.gain*gain,
+            gate: duration,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.envelope.is_finished()
+    }
+
+    /// Whether the note is not released yet
+    pub fn is_held(&self) -> bool {
+        self.gate > 0.0 && !self.is_finished()
+    }
+
+    pub fn next(&mut self, sample_rate: usize) -> f32 {
+        let sample_step = 1.0/sample_rate as f32;
+        if self.gate > 0.0 {
+            self.gate -= sample_step;
+            if self.gate <= 0.0 {
+                self.envelope.release();
+            }
+        }
+        self.oscillator.next(sample_rate)*self.envelope.next(sample_step)*self.gain
+    }
+}
+
+/// Recorded one-shot sound, mixed down to mono
+pub struct Sample {
+    pub sample_rate: usize,
+    pub frames: Vec<f32>,
+}
+
+impl Sample {
+    pub fn from_wav(wav: &crate::wav::Wav) -> Self {
+        Self {
+            sample_rate: wav.sample_rate,
+            frames: wav.samples
+                .chunks_exact(wav.channels)
+                .map(|frame| frame.iter().sum::<f32>()/wav.channels as f32)
+                .collect(),
+        }
+    }
+
+    fn frame(&self, index: isize) -> f32 {
+        usize::try_from(index).ok().and_then(|index| self.frames.get(index)).copied().unwrap_or(0.0)
+    }
+
+    /// The frames from `index - 1` to `index + 2`
+    fn frames_around(&self, index: isize) -> [f32; 4] {
+        [self.frame(index - 1), self.frame(index), self.frame(index + 1), self.frame(index + 2)]
+    }
+}
+
+/// Catmull-Rom interpolation at `t` between the middle two of the `frames`
+fn catmull_rom([y0, y1, y2, y3]: [f32; 4], t: f32) -> f32 {
+    let c1 = 0.5*(y2 - y0);
+    let c2 = y0 - 2.5*y1 + 2.0*y2 - 0.5*y3;
+    let c3 = 0.5*(y3 - y0) + 1.5*(y1 - y2);
+    ((c3*t + c2)*t + c1)*t + y1
+}
+
+/// Low-passes the frames of a sample just ahead of the playback, so pitching
+/// it up doesn't fold the frequencies above the new Nyquist frequency back
+/// into the audible range
+struct AntiAlias {
+    stages: [Biquad; 2],
+    /// The last 4 filtered frames, the newest at the back
+    history: [f32; 4],
+    /// Index of the next frame of the sample to go through the filter
+    next: isize,
+}
+
+impl AntiAlias {
+    /// `step` is how many frames of the sample one frame of the output advances
+    fn new(sample_rate: usize, step: f64) -> Self {
+        let cutoff = ANTI_ALIAS_CUTOFF*sample_rate as f64/2.0/step;
+        Self {
+            stages: ANTI_ALIAS_Q.map(|q| Biquad::low_pass(sample_rate, cutoff, q)),
+            history: [0.0; 4],
+            next: -1,
+        }
+    }
+
+    /// The filtered frames from `index - 1` to `index + 2`. The `index` never goes back.
+    fn frames_around(&mut self, sample: &Sample, index: isize) -> [f32; 4] {
+        while self.next <= index + 2 {
+            let filtered = self.stages.iter_mut().fold(sample.frame(self.next) as f64, |x, stage| stage.process(x));
+            self.history.rotate_left(1);
+            self.history[3] = filtered as f32;
+            self.next += 1;
+        }
+        self.history
+    }
+}
+
+/// Plays a [`Sample`] once, resampled so it sounds at the frequency of the note
+pub struct SamplePlayer {
+    sample: Arc<Sample>,
+    /// Position in the frames of the sample
+    position: f64,
+    /// How many frames of the sample one frame of the recording sounds like
+    pitch: f64,
+    gain: f32,
+    /// Created once the sample turns out to be pitched up
+    anti_alias: Option<AntiAlias>,
+}
+
+impl SamplePlayer {
+    pub fn new(sample: Arc<Sample>, freq: f32, gain: f32) -> Self {
+        Self {
+            sample,
+            position: 0.0,
+            pitch: (freq/SAMPLE_ROOT_FREQ) as f64,
+            gain,
+            anti_alias: None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.sample.frames.len() as f64
+    }
+
+    pub fn next(&mut self, sample_rate: usize) -> f32 {
+        let step = self.pitch*self.sample.sample_rate as f64/sample_rate as f64;
+        let index = self.position.floor() as isize;
+        let frames = if step > 1.0 {
+            self.anti_alias
+                .get_or_insert_with(|| AntiAlias::new(self.sample.sample_rate, step))
+                .frames_around(&self.sample, index)
+        } else {
+            self.sample.frames_around(index)
+        };
+        let sample = catmull_rom(frames, (self.position - self.position.floor()) as f32)*self.gain;
+        self.position += step;
+        sample
+    }
+}
+
+/// Anything that can play a note
+pub enum Voice {
+    Tone(Tone),
+    Sample(SamplePlayer),
+}
+
+impl Voice {
+    pub fn is_finished(&self) -> bool {
+        match self {
+            Voice::Tone(tone) => tone.is_finished(),
+            Voice::Sample(player) => player.is_finished(),
+        }
+    }
+
+    /// Whether the key of the note would still be down. The one-shots are
+    /// held for as long as they play.
+    pub fn is_held(&self) -> bool {
+        match self {
+            Voice::Tone(tone) => tone.is_held(),
+            Voice::Sample(player) => !player.is_finished(),
+        }
+    }
+
+    pub fn next(&mut self, sample_rate: usize) -> f32 {
+        match self {
+            Voice::Tone(tone) => tone.next(sample_rate),
+            Voice::Sample(player) => player.next(sample_rate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    fn sine_sample(freq: f32) -> Arc<Sample> {
+        use std::f32::consts::PI;
+        Arc::new(Sample {
+            sample_rate: SAMPLE_RATE,
+            frames: (0..SAMPLE_RATE).map(|i| (2.0*PI*freq*i as f32/SAMPLE_RATE as f32).sin()).collect(),
+        })
+    }
+
+    /// RMS of the sample played an octave up, past the start of the filter
+    fn octave_up_rms(sample: Arc<Sample>) -> f32 {
+        let mut player = SamplePlayer::new(sample, 2.0*SAMPLE_ROOT_FREQ, 1.0);
+        let output: Vec<f32> = (0..SAMPLE_RATE/2).map(|_| player.next(SAMPLE_RATE)).collect();
+        let tail = &output[SAMPLE_RATE/10..SAMPLE_RATE/2 - 100];
+        (tail.iter().map(|x| x*x).sum::<f32>()/tail.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn pitching_up_keeps_the_pass_band() {
+        let rms = octave_up_rms(sine_sample(1000.0));
+        assert!((rms - 0.5f32.sqrt()).abs() < 0.05, "rms {rms}");
+    }
+
+    #[test]
+    fn pitching_up_does_not_alias() {
+        // 15 kHz an octave up would fold back to 18 kHz
+        let rms = octave_up_rms(sine_sample(15000.0));
+        assert!(rms < 0.02, "rms {rms}");
+    }
+
+    /// Envelope times that are whole numbers of samples
+    const ADSR: Adsr = Adsr {attack: 0.01, decay: 0.02, sustain: 0.5, release: 0.03};
+
+    fn samples(seconds: f32) -> usize {
+        (seconds*SAMPLE_RATE as f32).round() as usize
+    }
+
+    fn play(oscillator: &mut Oscillator, count: usize) -> Vec<f32> {
+        (0..count).map(|_| oscillator.next(SAMPLE_RATE)).collect()
+    }
+
+    /// Energy of the spectrum outside of the harmonics of `freq` that are
+    /// below the Nyquist frequency, i.e. what got folded back
+    fn alias_energy(signal: &[f32], freq: f32) -> f32 {
+        use crate::fft::Spectrum;
+        let mut spectrum = Spectrum::new(signal.len());
+        let bin_width = SAMPLE_RATE as f32/signal.len() as f32;
+        spectrum.analyze(signal).iter().enumerate()
+            .filter(|(bin, _)| {
+                let harmonic = *bin as f32*bin_width/freq;
+                (harmonic - harmonic.round()).abs()*freq > 4.0*bin_width
+            })
+            .map(|(_, magnitude)| magnitude*magnitude)
+            .sum()
+    }
+
+    #[test]
+    fn poly_blep_splits_the_step() {
+        let dt = 0.01;
+        // Right at the wrap the step is halfway, and it's done a sample away
+        assert_eq!(poly_blep(0.0, dt), -1.0);
+        assert_eq!(poly_blep(dt, dt), 0.0);
+        assert_eq!(poly_blep(0.5, dt), 0.0);
+        assert!((poly_blep(1.0 - dt/2.0, dt) - 0.25).abs() < 1e-4);
+        let mut saw = Oscillator::new(Waveform::Saw, 480.0, 0);
+        assert_eq!(saw.next(SAMPLE_RATE), 0.0);
+    }
+
+    #[test]
+    fn poly_blep_reduces_aliasing() {
+        // Not a divisor of the sample rate, so the aliases land between the harmonics
+        let freq = 2345.6;
+        let len = 8192;
+        let dt = freq/SAMPLE_RATE as f32;
+        let phases: Vec<f32> = (0..len).map(|n| (n as f32*dt).fract()).collect();
+        let naive_square: Vec<f32> = phases.iter().map(|phase| if *phase < 0.5 {1.0} else {-1.0}).collect();
+        let naive_saw: Vec<f32> = phases.iter().map(|phase| 2.0*phase - 1.0).collect();
+        for (waveform, naive) in [(Waveform::Square, naive_square), (Waveform::Saw, naive_saw)] {
+            let blep = play(&mut Oscillator::new(waveform, freq, 0), len);
+            let (blep, naive) = (alias_energy(&blep, freq), alias_energy(&naive, freq));
+            assert!(blep < naive/10.0, "{waveform:?} aliases {blep} against {naive} of the naive one");
+        }
+    }
+
+    #[test]
+    fn waveforms_range_and_period() {
+        // 100 samples per period
+        let period = 100;
+        for waveform in [Waveform::Sine, Waveform::Square, Waveform::Saw, Waveform::Triangle] {
+            let output = play(&mut Oscillator::new(waveform, 480.0, 0), 10*period);
+            let (min, max) = output.iter().fold((f32::MAX, f32::MIN), |(min, max), x| (min.min(*x), max.max(*x)));
+            assert!((-1.01..-0.9).contains(&min) && (0.9..=1.01).contains(&max), "{waveform:?} goes from {min} to {max}");
+            for n in period..output.len() {
+                assert!((output[n] - output[n - period]).abs() < 0.01, "{waveform:?} repeats {} as {}", output[n - period], output[n]);
+            }
+            let mean = output.iter().sum::<f32>()/output.len() as f32;
+            assert!(mean.abs() < 0.01, "{waveform:?} has DC {mean}");
+        }
+        let noise = play(&mut Oscillator::new(Waveform::Noise, 480.0, 1), SAMPLE_RATE);
+        assert!(noise.iter().all(|x| (-1.0..=1.0).contains(x)));
+        let mean = noise.iter().sum::<f32>()/noise.len() as f32;
+        assert!(mean.abs() < 0.02, "noise has DC {mean}");
+    }
+
+    #[test]
+    fn noise_depends_on_the_seed() {
+        let a = play(&mut Oscillator::new(Waveform::Noise, 480.0, 1), 100);
+        let b = play(&mut Oscillator::new(Waveform::Noise, 480.0, 2), 100);
+        assert_eq!(a, play(&mut Oscillator::new(Waveform::Noise, 480.0, 1), 100));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn adsr_stages() {
+        let step = 1.0/SAMPLE_RATE as f32;
+        let mut envelope = Envelope::new(ADSR);
+        let levels: Vec<f32> = (0..samples(ADSR.attack + ADSR.decay) + 100).map(|_| envelope.next(step)).collect();
+        // The peak is reached at the end of the attack, give or take a sample
+        let peak = levels.iter().position(|level| *level == 1.0).unwrap();
+        assert!(peak.abs_diff(samples(ADSR.attack)) <= 1, "peak at {peak}");
+        assert!(levels[..peak].windows(2).all(|w| w[0] < w[1]));
+        let settled = levels.iter().position(|level| *level == ADSR.sustain).unwrap();
+        assert!(settled.abs_diff(samples(ADSR.attack + ADSR.decay)) <= 1, "sustain at {settled}");
+        assert!(levels[settled..].iter().all(|level| *level == ADSR.sustain));
+
+        envelope.release();
+        let release = (0..).position(|_| {
+            envelope.next(step);
+            envelope.is_finished()
+        }).unwrap();
+        assert!(release.abs_diff(samples(ADSR.release)) <= 1, "released in {release}");
+        assert_eq!(envelope.next(step), 0.0);
+    }
+
+    #[test]
+    fn release_during_attack() {
+        let step = 1.0/SAMPLE_RATE as f32;
+        let mut envelope = Envelope::new(ADSR);
+        let mut level = 0.0;
+        for _ in 0..samples(ADSR.attack/2.0) {
+            level = envelope.next(step);
+        }
+        assert!((level - 0.5).abs() < 0.01, "level {level}");
+        envelope.release();
+        // Goes down from where it was, in the time of the release
+        let mut count: usize = 0;
+        while !envelope.is_finished() {
+            let next = envelope.next(step);
+            assert!(next < level);
+            level = next;
+            count += 1;
+        }
+        assert!(count.abs_diff(samples(ADSR.release)) <= 1, "released in {count}");
+    }
+
+    #[test]
+    fn gain_of_the_voice() {
+        let instrument = Instrument {waveform: Waveform::Square, envelope: ADSR, gain: 0.5};
+        let mut loud = Tone::new(&instrument, 440.0, 1.0, 0.05, 7);
+        let mut quiet = Tone::new(&instrument, 440.0, 0.2, 0.05, 7);
+        let mut peak: f32 = 0.0;
+        while !loud.is_finished() {
+            let (loud, quiet) = (loud.next(SAMPLE_RATE), quiet.next(SAMPLE_RATE));
+            assert!((quiet - 0.2*loud).abs() < 1e-6);
+            peak = peak.max(quiet.abs());
+        }
+        assert!(quiet.is_finished());
+        // Both the gain of the instrument and of the voice
+        assert!((peak - 0.5*0.2).abs() < 1e-3, "peak {peak}");
+    }
+
+    #[test]
+    fn original_pitch_plays_the_frames() {
+        let sample = sine_sample(1000.0);
+        let mut player = SamplePlayer::new(Arc::clone(&sample), SAMPLE_ROOT_FREQ, 1.0);
+        for frame in sample.frames.iter() {
+            assert_eq!(player.next(SAMPLE_RATE), *frame);
+        }
+        assert!(player.is_finished());
+    }
+}
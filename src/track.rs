@@ -0,0 +1,158 @@
+//! External audio track laid under the sound of the simulation, e.g. a
+//! music bed for the video
+use crate::resample::{self, Resampler};
+use crate::wav::Wav;
+
+/// The whole track converted to the format of the sound of the simulation
+#[derive(Clone)]
+pub struct Track {
+    channels: usize,
+    /// Interleaved samples at the rate of the sound
+    samples: Vec<f32>,
+    /// Frame of the track under the next frame of the sound, negative while
+    /// the track has not started yet
+    position: isize,
+    /// Gain of the track relative to the file
+    level: f32,
+    /// Applied on top of the level, see [`Track::set_gain`]
+    gain: f32,
+}
+
+impl Track {
+    /// Converts the `wav` to `sample_rate` and `channels`. Mono tracks go to
+    /// all the channels, the channels the sound doesn't have are dropped.
+    /// The track starts `offset` seconds into the sound, a negative offset
+    /// skips the beginning of the track instead.
+    pub fn new(wav: &Wav, sample_rate: usize, channels: usize, quality: resample::Quality, gain_db: f32, offset: f64) -> Self {
+        let mut converted = Vec::with_capacity(wav.frames_count()*channels);
+        for frame in wav.samples.chunks_exact(wav.channels) {
+            for channel in 0..channels {
+                converted.push(if wav.channels == 1 {frame[0]} else {frame.get(channel).copied().unwrap_or(0.0)});
+            }
+        }
+
+        let mut resampler = Resampler::new(wav.sample_rate, sample_rate, channels, quality);
+        let mut samples = Vec::new();
+        resampler.process(&converted, &mut samples);
+        resampler.finish(&mut samples);
+
+        Self {
+            channels,
+            samples,
+            position: -(offset*sample_rate as f64).round() as isize,
+            level: 10f32.powf(gain_db/20.0),
+            gain: 1.0,
+        }
+    }
+
+    /// Seconds from the start of the sound to the end of the track
+    pub fn end(&self, sample_rate: usize) -> f64 {
+        let frames = (self.samples.len()/self.channels) as isize - self.position;
+        frames.max(0) as f64/sample_rate as f64
+    }
+
+    /// Gain applied on top of the level of the track, used by the loudness
+    /// normalization the same way as [`crate::sim::State::set_gain`]
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    /// Adds the next samples of the track to the interleaved `sound`, or
+    /// overwrites it with them if `replace` is set
+    pub fn mix(&mut self, sound: &mut [f32], replace: bool) {
+        let gain = self.level*self.gain;
+        for frame in sound.chunks_exact_mut(self.channels) {
+            let track = usize::try_from(self.position).ok()
+                .and_then(|position| self.samples.get(position*self.channels..(position + 1)*self.channels));
+            for (index, sample) in frame.iter_mut().enumerate() {
+                let value = track.map_or(0.0, |track| track[index]*gain);
+                if replace {
+                    *sample = value;
+                } else {
+                    *sample += value;
+                }
+            }
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    /// At the rate of the sound, so the resampler passes the samples through
+    fn track(channels: usize, samples: &[f32], gain_db: f32, offset_frames: isize) -> Track {
+        let wav = Wav {sample_rate: SAMPLE_RATE, channels, samples: samples.to_vec()};
+        Track::new(&wav, SAMPLE_RATE, 2, resample::Quality::default(), gain_db, offset_frames as f64/SAMPLE_RATE as f64)
+    }
+
+    fn mixed(track: &mut Track, frames: usize, replace: bool) -> Vec<f32> {
+        let mut sound = vec![1.0; 2*frames];
+        track.mix(&mut sound, replace);
+        sound
+    }
+
+    #[test]
+    fn mono_goes_to_both_channels() {
+        let mut track = track(1, &[0.5, -0.25], 0.0, 0);
+        assert_eq!(mixed(&mut track, 3, true), [0.5, 0.5, -0.25, -0.25, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn extra_channels_are_dropped() {
+        let mut track = track(3, &[0.1, 0.2, 0.3, 0.4, 0.5, 0.6], 0.0, 0);
+        assert_eq!(mixed(&mut track, 2, true), [0.1, 0.2, 0.4, 0.5]);
+    }
+
+    #[test]
+    fn mix_adds_and_replace_overwrites() {
+        let mut track1 = track(2, &[0.5, -0.5], 0.0, 0);
+        assert_eq!(mixed(&mut track1, 2, false), [1.5, 0.5, 1.0, 1.0]);
+        let mut track2 = track(2, &[0.5, -0.5], 0.0, 0);
+        assert_eq!(mixed(&mut track2, 2, true), [0.5, -0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn positive_offset_delays_the_track() {
+        let mut track = track(1, &[0.5, 0.25], 0.0, 2);
+        assert_eq!(mixed(&mut track, 5, true), [0.0, 0.0, 0.0, 0.0, 0.5, 0.5, 0.25, 0.25, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn negative_offset_skips_the_start() {
+        let mut track = track(1, &[0.5, 0.25, 0.125], 0.0, -2);
+        assert_eq!(mixed(&mut track, 2, true), [0.125, 0.125, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn mixing_in_blocks_continues() {
+        let mut track = track(1, &[0.1, 0.2, 0.3], 0.0, 1);
+        let mut sound = Vec::new();
+        for frames in [1, 2, 1] {
+            sound.extend(mixed(&mut track, frames, true));
+        }
+        assert_eq!(sound, [0.0, 0.0, 0.1, 0.1, 0.2, 0.2, 0.3, 0.3]);
+    }
+
+    #[test]
+    fn end_includes_the_offset() {
+        let frames = |seconds: f64| (seconds*SAMPLE_RATE as f64).round() as isize;
+        assert_eq!(frames(track(1, &[0.0; 100], 0.0, 0).end(SAMPLE_RATE)), 100);
+        assert_eq!(frames(track(1, &[0.0; 100], 0.0, 50).end(SAMPLE_RATE)), 150);
+        assert_eq!(frames(track(1, &[0.0; 100], 0.0, -30).end(SAMPLE_RATE)), 70);
+        assert_eq!(track(1, &[0.0; 100], 0.0, -300).end(SAMPLE_RATE), 0.0);
+    }
+
+    #[test]
+    fn level_and_gain_multiply() {
+        let mut track = track(1, &[0.5, 0.5], -20.0, 0);
+        let sound = mixed(&mut track, 1, true);
+        assert!((sound[0] - 0.05).abs() < 1e-6, "{sound:?}");
+        track.set_gain(2.0);
+        let sound = mixed(&mut track, 1, true);
+        assert!((sound[0] - 0.1).abs() < 1e-6, "{sound:?}");
+    }
+}
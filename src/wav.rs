@@ -0,0 +1,381 @@
+//! WAV container
+//!
+//! https://learn.microsoft.com/en-us/windows/win32/xaudio2/resource-interchange-file-format--riff-
+//! https://tech.ebu.ch/docs/tech/tech3306v1_1.pdf (RF64)
+#![allow(non_upper_case_globals)]
+
+use std::fs;
+use std::io::{self, Write, Seek, SeekFrom};
+use crate::riff::*;
+
+const WAVE: u32 = 0x45564157;
+const RF64: u32 = 0x34364652;
+const JUNK: u32 = 0x4b4e554a;
+const ds64: u32 = 0x34367364;
+const fmt_: u32 = 0x20746d66;
+const fact: u32 = 0x74636166;
+const data: u32 = 0x61746164;
+
+/// Size of the `ds64` chunk content without the table: RIFF size, data size
+/// and sample count as u64 plus the table length as u32. Reserved upfront as
+/// a `JUNK` chunk in case the file outgrows 4 GB.
+const DS64_SIZE: usize = 3*size_of::<u64>() + size_of::<u32>();
+
+/// Offsets of the fields patched by [`Writer::finish`]
+const RIFF_SIZE_OFFSET: u64 = 4;
+const JUNK_OFFSET: u64 = 12;
+const FACT_OFFSET: u64 = JUNK_OFFSET + 8 + DS64_SIZE as u64 + 8 + size_of::<WAVEFORMATEXTENSIBLE>() as u64;
+const DATA_OFFSET: u64 = FACT_OFFSET + 8 + 4;
+const HEADER_SIZE: u64 = DATA_OFFSET + 8;
+
+/// Size used by the streaming writers that can't go back and patch the header
+const UNKNOWN_SIZE: u32 = 0xFFFFFFFF;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes 32 bit float samples into a WAVE file
+///
+/// The sizes in the header are patched in [`Writer::finish`]. If the sink
+/// can't seek (a pipe or stdout) they are left as `0xFFFFFFFF` which most
+/// readers understand as "until the end of the stream".
+pub struct Writer<W: Write + Seek> {
+    sink: W,
+    format: WAVEFORMATEXTENSIBLE,
+    seekable: bool,
+    data_size: u64,
+}
+
+impl<W: Write + Seek> Writer<W> {
+    pub fn new(mut sink: W, sample_rate: usize, channels: usize) -> io::Result<Self> {
+        let seekable = sink.stream_position().is_ok();
+        let format = float_wave_format(sample_rate, channels);
+
+        sink.write_all(&RIFF.to_le_bytes())?;
+        sink.write_all(&UNKNOWN_SIZE.to_le_bytes())?;
+        sink.write_all(&WAVE.to_le_bytes())?;
+        write_chunk(&mut sink, &Chunk {
+            id: FOURCC::from_u32(JUNK),
+            content: &[0; DS64_SIZE],
+        })?;
+        write_chunk(&mut sink, &transmute_struct_to_chunk(FOURCC::from_u32(fmt_), &format))?;
+        write_chunk(&mut sink, &Chunk {
+            id: FOURCC::from_u32(fact),
+            content: &UNKNOWN_SIZE.to_le_bytes(),
+        })?;
+        sink.write_all(&data.to_le_bytes())?;
+        sink.write_all(&UNKNOWN_SIZE.to_le_bytes())?;
+
+        Ok(Self {sink, format, seekable, data_size: 0})
+    }
+
+    /// Appends interleaved samples
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            self.sink.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size += size_of_val(samples) as u64;
+        Ok(())
+    }
+
+    /// Back-patches the sizes and returns the sink. Switches the file to RF64
+    /// if it doesn't fit into the 32 bit sizes of RIFF.
+    pub fn finish(mut self) -> io::Result<W> {
+        // 32 bit samples never need the pad byte
+        let riff_size = HEADER_SIZE - 8 + self.data_size;
+        let sample_count = self.data_size/self.format.Format.nBlockAlign as u64;
+
+        if self.seekable {
+            if riff_size <= u32::MAX as u64 {
+                self.patch(RIFF_SIZE_OFFSET, &(riff_size as u32).to_le_bytes())?;
+                self.patch(FACT_OFFSET + 8, &(sample_count as u32).to_le_bytes())?;
+                self.patch(DATA_OFFSET + 4, &(self.data_size as u32).to_le_bytes())?;
+            } else {
+                let mut ds64_chunk = Vec::with_capacity(8 + DS64_SIZE);
+                ds64_chunk.extend(ds64.to_le_bytes());
+                ds64_chunk.extend((DS64_SIZE as u32).to_le_bytes());
+                ds64_chunk.extend(riff_size.to_le_bytes());
+                ds64_chunk.extend(self.data_size.to_le_bytes());
+                ds64_chunk.extend(sample_count.to_le_bytes());
+                ds64_chunk.extend(0u32.to_le_bytes());
+                self.patch(0, &RF64.to_le_bytes())?;
+                self.patch(JUNK_OFFSET, &ds64_chunk)?;
+            }
+            self.sink.seek(SeekFrom::End(0))?;
+        }
+
+        self.sink.flush()?;
+        Ok(self.sink)
+    }
+
+    fn patch(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.sink.seek(SeekFrom::Start(offset))?;
+        self.sink.write_all(bytes)
+    }
+}
+
+/// Decoded content of a WAVE file
+pub struct Wav {
+    pub sample_rate: usize,
+    pub channels: usize,
+    /// Interleaved samples normalized to [-1.0; 1.0]
+    pub samples: Vec<f32>,
+}
+
+impl Wav {
+    pub fn frames_count(&self) -> usize {
+        self.samples.len()/self.channels
+    }
+}
+
+fn chop_bytes<'a>(content: &mut &'a [u8], n: usize, what: &str) -> io::Result<&'a [u8]> {
+    if content.len() < n {
+        return Err(invalid_data(format!("unexpected end of file while reading {what}")));
+    }
+    let (result, rest) = content.split_at(n);
+    *content = rest;
+    Ok(result)
+}
+
+fn chop_u32_checked(content: &mut &[u8], what: &str) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(chop_bytes(content, 4, what)?.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Decodes the samples of `data` according to the `fmt ` chunk
+fn decode_samples(format_tag: WORD, bits: WORD, data_bytes: &[u8]) -> io::Result<Vec<f32>> {
+    let bytes = bits as usize/8;
+    let samples = data_bytes.chunks_exact(bytes);
+    match (format_tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => Ok(samples.map(|s| (s[0] as f32 - 128.0)/128.0).collect()),
+        (WAVE_FORMAT_PCM, 16) => Ok(samples.map(|s| i16::from_le_bytes([s[0], s[1]]) as f32/32768.0).collect()),
+        (WAVE_FORMAT_PCM, 24) => Ok(samples.map(|s| (i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) as f32/8388608.0).collect()),
+        (WAVE_FORMAT_PCM, 32) => Ok(samples.map(|s| i32::from_le_bytes(s.try_into().unwrap()) as f32/2147483648.0).collect()),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => Ok(samples.map(|s| f32::from_le_bytes(s.try_into().unwrap())).collect()),
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => Ok(samples.map(|s| f64::from_le_bytes(s.try_into().unwrap()) as f32).collect()),
+        _ => Err(invalid_data(format!("unsupported sample format {format_tag:#x} with {bits} bits per sample"))),
+    }
+}
+
+/// Parses a RIFF or RF64 WAVE file with PCM or IEEE float samples
+pub fn parse(mut content: &[u8]) -> io::Result<Wav> {
+    let magic = chop_u32_checked(&mut content, "RIFF header")?;
+    if magic != RIFF && magic != RF64 {
+        return Err(invalid_data("not a RIFF file".to_string()));
+    }
+    let _riff_size = chop_u32_checked(&mut content, "RIFF header")?;
+    if chop_u32_checked(&mut content, "RIFF header")? != WAVE {
+        return Err(invalid_data("not a WAVE file".to_string()));
+    }
+
+    let mut ds64_data_size = None;
+    let mut format = None;
+    while !content.is_empty() {
+        let id = chop_u32_checked(&mut content, "chunk header")?;
+        let size = chop_u32_checked(&mut content, "chunk header")?;
+        if id == data {
+            let size = match (size, ds64_data_size) {
+                (UNKNOWN_SIZE, Some(size)) => size as usize,
+                (UNKNOWN_SIZE, None) => content.len(),
+                (size, _) => size as usize,
+            };
+            let Some((format_tag, channels, sample_rate, bits)) = format else {
+                return Err(invalid_data("data chunk before fmt chunk".to_string()));
+            };
+            // Streamed files may end before the declared size, possibly in
+            // the middle of a frame
+            let size = size.min(content.len());
+            let data_bytes = &content[..size - size%(channels*bits as usize/8)];
+            return Ok(Wav {
+                sample_rate,
+                channels,
+                samples: decode_samples(format_tag, bits, data_bytes)?,
+            });
+        }
+
+        let chunk = chop_bytes(&mut content, size as usize, "chunk")?;
+        if size%2 != 0 && !content.is_empty() {
+            chop_bytes(&mut content, 1, "chunk padding")?;
+        }
+        match id {
+            ds64 if chunk.len() >= DS64_SIZE => {
+                ds64_data_size = Some(read_u64(chunk, 8));
+            }
+            fmt_ => {
+                if chunk.len() < size_of::<WAVEFORMATEX>() - size_of::<WORD>() {
+                    return Err(invalid_data("fmt chunk is too small".to_string()));
+                }
+                let word = |offset: usize| WORD::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+                let mut format_tag = word(0);
+                let channels = word(2) as usize;
+                let sample_rate = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as usize;
+                let bits = word(14);
+                if format_tag == WAVE_FORMAT_EXTENSIBLE {
+                    if chunk.len() < size_of::<WAVEFORMATEXTENSIBLE>() {
+                        return Err(invalid_data("extensible fmt chunk is too small".to_string()));
+                    }
+                    // The first 2 bytes of the SubFormat GUID are the actual format tag
+                    format_tag = word(24);
+                }
+                if channels == 0 || bits == 0 || bits%8 != 0 {
+                    return Err(invalid_data(format!("invalid format: {channels} channels, {bits} bits per sample")));
+                }
+                format = Some((format_tag, channels, sample_rate, bits));
+            }
+            _ => {}
+        }
+    }
+
+    Err(invalid_data("no data chunk".to_string()))
+}
+
+pub fn read(file_path: &str) -> io::Result<Wav> {
+    let bytes = fs::read(file_path)?;
+    parse(&bytes).map_err(|err| invalid_data(format!("{file_path}: {err}")))
+}
+
+/// Prints the format of a WAVE file. Mostly to check what [`Writer`] produces.
+pub fn main(file_path: Option<String>) -> io::Result<()> {
+    let Some(file_path) = file_path else {
+        eprintln!("ERROR: no input file is provided");
+        std::process::exit(1);
+    };
+    let wav = read(&file_path)?;
+    let peak = wav.samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    println!("{file_path}:");
+    println!("  Sample rate: {} Hz", wav.sample_rate);
+    println!("  Channels:    {}", wav.channels);
+    println!("  Duration:    {:.3} s ({} frames)", wav.frames_count() as f32/wav.sample_rate as f32, wav.frames_count());
+    println!("  Peak:        {:.3}", peak);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Plain `fmt ` chunk followed by a `data` chunk of the declared `data_size`
+    fn wave_file(format_tag: WORD, channels: usize, bits: usize, data_bytes: &[u8], data_size: u32) -> Vec<u8> {
+        let block_align = channels*bits/8;
+        let mut fmt_chunk = Vec::new();
+        fmt_chunk.extend(format_tag.to_le_bytes());
+        fmt_chunk.extend((channels as WORD).to_le_bytes());
+        fmt_chunk.extend(48000u32.to_le_bytes());
+        fmt_chunk.extend((48000*block_align as u32).to_le_bytes());
+        fmt_chunk.extend((block_align as WORD).to_le_bytes());
+        fmt_chunk.extend((bits as WORD).to_le_bytes());
+
+        let mut file = Vec::new();
+        file.extend(RIFF.to_le_bytes());
+        file.extend(UNKNOWN_SIZE.to_le_bytes());
+        file.extend(WAVE.to_le_bytes());
+        write_chunk(&mut file, &Chunk {id: FOURCC::from_u32(fmt_), content: &fmt_chunk}).unwrap();
+        file.extend(data.to_le_bytes());
+        file.extend(data_size.to_le_bytes());
+        file.extend(data_bytes);
+        file
+    }
+
+    fn decode(format_tag: WORD, bits: usize, data_bytes: &[u8]) -> Vec<f32> {
+        let wav = parse(&wave_file(format_tag, 1, bits, data_bytes, data_bytes.len() as u32)).unwrap();
+        assert_eq!(wav.sample_rate, 48000);
+        assert_eq!(wav.channels, 1);
+        wav.samples
+    }
+
+    #[test]
+    fn writer_round_trip() {
+        let samples: Vec<f32> = (0..1000).map(|i| (i as f32*0.01).sin()).collect();
+        let mut writer = Writer::new(Cursor::new(Vec::new()), 44100, 2).unwrap();
+        writer.write(&samples[..600]).unwrap();
+        writer.write(&samples[600..]).unwrap();
+        let file = writer.finish().unwrap().into_inner();
+        assert_eq!(file.len() as u64, HEADER_SIZE + 4*1000);
+
+        let wav = parse(&file).unwrap();
+        assert_eq!(wav.sample_rate, 44100);
+        assert_eq!(wav.channels, 2);
+        assert_eq!(wav.frames_count(), 500);
+        assert_eq!(wav.samples, samples);
+    }
+
+    #[test]
+    fn pcm_decoding() {
+        assert_eq!(decode(WAVE_FORMAT_PCM, 8, &[0, 128, 255]), [-1.0, 0.0, 127.0/128.0]);
+        assert_eq!(decode(WAVE_FORMAT_PCM, 16, &[0x00, 0x80, 0x00, 0x00, 0xFF, 0x7F]), [-1.0, 0.0, 32767.0/32768.0]);
+        assert_eq!(decode(WAVE_FORMAT_PCM, 24, &[0x00, 0x00, 0x80, 0x00, 0x00, 0x40, 0xFF, 0xFF, 0xFF]), [-1.0, 0.5, -1.0/8388608.0]);
+        assert_eq!(decode(WAVE_FORMAT_PCM, 32, &[0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0xC0]), [-1.0, -0.5]);
+    }
+
+    #[test]
+    fn float_decoding() {
+        let mut bytes = Vec::new();
+        bytes.extend(0.25f32.to_le_bytes());
+        bytes.extend((-1.0f32).to_le_bytes());
+        assert_eq!(decode(WAVE_FORMAT_IEEE_FLOAT, 32, &bytes), [0.25, -1.0]);
+
+        let mut bytes = Vec::new();
+        bytes.extend(0.125f64.to_le_bytes());
+        bytes.extend((-0.75f64).to_le_bytes());
+        assert_eq!(decode(WAVE_FORMAT_IEEE_FLOAT, 64, &bytes), [0.125, -0.75]);
+    }
+
+    #[test]
+    fn rf64_data_size_comes_from_ds64() {
+        let samples = [0.5f32, -0.5, 0.25, 1.0];
+        let data_bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        // Trailing bytes past the size in ds64 are not samples
+        let mut tail = data_bytes.clone();
+        tail.extend([0xAA; 8]);
+        let mut file = wave_file(WAVE_FORMAT_IEEE_FLOAT, 2, 32, &tail, UNKNOWN_SIZE);
+
+        let mut ds64_content = Vec::new();
+        ds64_content.extend((file.len() as u64).to_le_bytes());
+        ds64_content.extend((data_bytes.len() as u64).to_le_bytes());
+        ds64_content.extend(2u64.to_le_bytes());
+        ds64_content.extend(0u32.to_le_bytes());
+        let mut ds64_chunk = Vec::new();
+        write_chunk(&mut ds64_chunk, &Chunk {id: FOURCC::from_u32(ds64), content: &ds64_content}).unwrap();
+        file[..4].copy_from_slice(&RF64.to_le_bytes());
+        file.splice(12..12, ds64_chunk);
+
+        let wav = parse(&file).unwrap();
+        assert_eq!(wav.channels, 2);
+        assert_eq!(wav.samples, samples);
+    }
+
+    #[test]
+    fn truncated_data_keeps_whole_frames() {
+        // 2 channels of 16 bits, the declared 100 bytes end after 2.5 frames
+        let file = wave_file(WAVE_FORMAT_PCM, 2, 16, &[0, 0x40, 0, 0xC0, 0, 0x20, 0, 0xE0, 0, 0x10], 100);
+        let wav = parse(&file).unwrap();
+        assert_eq!(wav.samples, [0.5, -0.5, 0.25, -0.25]);
+        assert_eq!(wav.frames_count(), 2);
+    }
+
+    #[test]
+    fn odd_sized_chunk_is_padded() {
+        let mut file = wave_file(WAVE_FORMAT_PCM, 1, 16, &[0, 0x40], 2);
+        let data_offset = file.len() - 2 - 8;
+        let mut list = Vec::new();
+        write_chunk(&mut list, &Chunk {id: FOURCC::from_u32(JUNK), content: &[1, 2, 3]}).unwrap();
+        assert_eq!(list.len(), 8 + 4);
+        file.splice(data_offset..data_offset, list);
+        assert_eq!(parse(&file).unwrap().samples, [0.5]);
+    }
+
+    #[test]
+    fn malformed_formats_are_rejected() {
+        assert!(parse(&wave_file(WAVE_FORMAT_PCM, 1, 0, &[0; 4], 4)).is_err());
+        assert!(parse(&wave_file(WAVE_FORMAT_PCM, 1, 12, &[0; 4], 4)).is_err());
+        assert!(parse(&wave_file(WAVE_FORMAT_PCM, 0, 16, &[0; 4], 4)).is_err());
+        assert!(parse(&wave_file(WAVE_FORMAT_IEEE_FLOAT, 1, 16, &[0; 4], 4)).is_err());
+        assert!(parse(&wave_file(WAVE_FORMAT_PCM, 1, 16, &[0; 4], 4)[..30]).is_err());
+        assert!(parse(b"RIFF\0\0\0\0AVI ").is_err());
+    }
+}
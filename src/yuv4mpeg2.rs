@@ -1,5 +1,19 @@
 //! YUV4MPEG2 container
 use std::io::{self, Write};
+use std::thread;
+use crate::config::{Interlace, VideoFormat};
+
+/// `[offset, r, g, b]` coefficients of [`YCbCr::from_rgb`] for the vectorised
+/// path, the subtractions turned into negative coefficients
+#[cfg(target_arch = "x86_64")]
+const Y_COEFFS: [f32; 4] = [16.0, 65.738, 129.057, 25.064];
+#[cfg(target_arch = "x86_64")]
+const CB_COEFFS: [f32; 4] = [128.0, -37.945, -74.494, 112.439];
+#[cfg(target_arch = "x86_64")]
+const CR_COEFFS: [f32; 4] = [128.0, 112.439, -94.154, -18.285];
+
+/// Frames smaller than that are not worth spawning threads for
+const MIN_PIXELS_PER_THREAD: usize = 64*1024;
 
 struct YCbCr {
     y: u8,
@@ -8,6 +22,9 @@ struct YCbCr {
 }
 
 impl YCbCr {
+    /// Reference implementation of the conversion. The vectorised path does
+    /// the same f32 operations in the same order, so it produces exactly the
+    /// same bytes.
     fn from_rgb(pixel: u32) -> Self {
         let rf = ((pixel >> (8*2)) & 0xFF) as f32;
         let gf = ((pixel >> (8*1)) & 0xFF) as f32;
@@ -19,46 +36,237 @@ impl YCbCr {
     }
 }
 
+fn convert_scalar(canvas: &[u32], y_plane: &mut [u8], cb_plane: &mut [u8], cr_plane: &mut [u8]) {
+    for (i, pixel) in canvas.iter().enumerate() {
+        let YCbCr{y, cb, cr} = YCbCr::from_rgb(*pixel);
+        y_plane[i] = y;
+        cb_plane[i] = cb;
+        cr_plane[i] = cr;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+    use std::ptr;
+    use super::*;
+
+    /// `offset + k*x/256.0` for every channel from left to right, truncated
+    /// like `as` does. Subtracting is the same as adding the negated term,
+    /// and no FMA is used, so every step rounds like the scalar code.
+    #[inline(always)]
+    unsafe fn apply(r: __m256, g: __m256, b: __m256, [offset, kr, kg, kb]: [f32; 4]) -> __m256i {
+        let scale = _mm256_set1_ps(256.0);
+        let mut sum = _mm256_set1_ps(offset);
+        sum = _mm256_add_ps(sum, _mm256_div_ps(_mm256_mul_ps(_mm256_set1_ps(kr), r), scale));
+        sum = _mm256_add_ps(sum, _mm256_div_ps(_mm256_mul_ps(_mm256_set1_ps(kg), g), scale));
+        sum = _mm256_add_ps(sum, _mm256_div_ps(_mm256_mul_ps(_mm256_set1_ps(kb), b), scale));
+        _mm256_cvttps_epi32(sum)
+    }
+
+    /// Stores 8 lanes saturated to bytes into `dst[0..8]`
+    #[inline(always)]
+    unsafe fn store_u8x8(dst: *mut u8, lanes: __m256i) {
+        // Packing works within each 128-bit half, so the first 4 bytes of
+        // every half hold the 4 lanes of that half
+        let packed = _mm256_packus_epi32(lanes, lanes);
+        let packed = _mm256_packus_epi16(packed, packed);
+        ptr::write_unaligned(dst as *mut i32, _mm256_extract_epi32::<0>(packed));
+        ptr::write_unaligned(dst.add(4) as *mut i32, _mm256_extract_epi32::<4>(packed));
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn convert(canvas: &[u32], y_plane: &mut [u8], cb_plane: &mut [u8], cr_plane: &mut [u8]) {
+        const LANES: usize = 8;
+        let n = canvas.len()/LANES*LANES;
+        let mask = _mm256_set1_epi32(0xFF);
+        for i in (0..n).step_by(LANES) {
+            let pixels = _mm256_loadu_si256(canvas.as_ptr().add(i) as *const __m256i);
+            let r = _mm256_cvtepi32_ps(_mm256_and_si256(_mm256_srli_epi32::<{8*2}>(pixels), mask));
+            let g = _mm256_cvtepi32_ps(_mm256_and_si256(_mm256_srli_epi32::<{8*1}>(pixels), mask));
+            let b = _mm256_cvtepi32_ps(_mm256_and_si256(pixels, mask));
+            store_u8x8(y_plane.as_mut_ptr().add(i), apply(r, g, b, Y_COEFFS));
+            store_u8x8(cb_plane.as_mut_ptr().add(i), apply(r, g, b, CB_COEFFS));
+            store_u8x8(cr_plane.as_mut_ptr().add(i), apply(r, g, b, CR_COEFFS));
+        }
+        convert_scalar(&canvas[n..], &mut y_plane[n..], &mut cb_plane[n..], &mut cr_plane[n..]);
+    }
+}
+
+fn convert(canvas: &[u32], y_plane: &mut [u8], cb_plane: &mut [u8], cr_plane: &mut [u8]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2 and all the planes are as long as the canvas
+        unsafe { avx2::convert(canvas, y_plane, cb_plane, cr_plane) };
+        return;
+    }
+    convert_scalar(canvas, y_plane, cb_plane, cr_plane);
+}
+
+/// Splits the canvas into `threads` parts converted at the same time
+fn convert_parallel(threads: usize, canvas: &[u32], y_plane: &mut [u8], cb_plane: &mut [u8], cr_plane: &mut [u8]) {
+    if threads <= 1 {
+        convert(canvas, y_plane, cb_plane, cr_plane);
+        return;
+    }
+
+    let chunk = canvas.len().div_ceil(threads);
+    thread::scope(|s| {
+        let parts = canvas.chunks(chunk)
+            .zip(y_plane.chunks_mut(chunk))
+            .zip(cb_plane.chunks_mut(chunk))
+            .zip(cr_plane.chunks_mut(chunk));
+        for (((canvas, y_plane), cb_plane), cr_plane) in parts {
+            s.spawn(|| convert(canvas, y_plane, cb_plane, cr_plane));
+        }
+    });
+}
 
 #[derive(Default)]
 struct Frame {
     y_plane: Vec<u8>,
     cb_plane: Vec<u8>,
     cr_plane: Vec<u8>,
+    a_plane: Vec<u8>,
 }
 
 impl Frame {
+    fn alpha_from_canvas(&mut self, canvas: &[u32]) {
+        self.a_plane.clear();
+        self.a_plane.extend(canvas.iter().map(|pixel| ((pixel >> (8*3)) & 0xFF) as u8));
+    }
+
     fn from_canvas(&mut self, canvas: &[u32]) {
-        self.y_plane.clear();
-        self.cb_plane.clear();
-        self.cr_plane.clear();
-        for pixel in canvas {
-            let YCbCr{y, cb, cr} = YCbCr::from_rgb(*pixel);
-            self.y_plane.push(y);
-            self.cb_plane.push(cb);
-            self.cr_plane.push(cr);
-        }
+        self.y_plane.resize(canvas.len(), 0);
+        self.cb_plane.resize(canvas.len(), 0);
+        self.cr_plane.resize(canvas.len(), 0);
+
+        let threads = thread::available_parallelism().map_or(1, |n| n.get())
+            .min(canvas.len()/MIN_PIXELS_PER_THREAD)
+            .max(1);
+        convert_parallel(threads, canvas, &mut self.y_plane, &mut self.cb_plane, &mut self.cr_plane);
     }
 }
 
 #[derive(Default)]
 pub struct Container {
     frame: Frame,
+    alpha: bool,
 }
 
 impl Container {
     /// Prepare the metadata header for the YUV4MPEG2 container
-    pub fn start(&mut self, sink: &mut impl Write, width: usize, height: usize, fps: usize) -> io::Result<()> {
-        writeln!(sink, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, fps)
+    pub fn start(&mut self, sink: &mut impl Write, format: VideoFormat) -> io::Result<()> {
+        let VideoFormat {width, height, fps, pixel_aspect, interlace, alpha} = format;
+        self.alpha = alpha;
+        let interlace = match interlace {
+            Interlace::Progressive => 'p',
+            Interlace::TopFieldFirst => 't',
+            Interlace::BottomFieldFirst => 'b',
+        };
+        let colorspace = if alpha {"444alpha"} else {"444"};
+        writeln!(sink, "YUV4MPEG2 W{} H{} F{}:{} I{} A{}:{} C{}",
+                 width, height, fps.num, fps.den, interlace, pixel_aspect.num, pixel_aspect.den, colorspace)
     }
 
     /// Emit a frame into YUV4MPEG2 container
     pub fn frame(&mut self, sink: &mut impl Write, canvas: &[u32]) -> io::Result<()> {
         self.frame.from_canvas(canvas);
         writeln!(sink, "FRAME")?;
-        sink.write(&self.frame.y_plane)?;
-        sink.write(&self.frame.cb_plane)?;
-        sink.write(&self.frame.cr_plane)?;
+        sink.write_all(&self.frame.y_plane)?;
+        sink.write_all(&self.frame.cb_plane)?;
+        sink.write_all(&self.frame.cr_plane)?;
+        if self.alpha {
+            self.frame.alpha_from_canvas(canvas);
+            sink.write_all(&self.frame.a_plane)?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Planes {
+        y: Vec<u8>,
+        cb: Vec<u8>,
+        cr: Vec<u8>,
+    }
+
+    fn planes(canvas: &[u32], convert: impl FnOnce(&[u32], &mut [u8], &mut [u8], &mut [u8])) -> Planes {
+        let mut planes = Planes {y: vec![0; canvas.len()], cb: vec![0; canvas.len()], cr: vec![0; canvas.len()]};
+        convert(canvas, &mut planes.y, &mut planes.cb, &mut planes.cr);
+        planes
+    }
+
+    fn assert_same(actual: &Planes, expected: &Planes) {
+        assert!(actual.y == expected.y, "Y planes differ");
+        assert!(actual.cb == expected.cb, "Cb planes differ");
+        assert!(actual.cr == expected.cr, "Cr planes differ");
+    }
+
+    /// Deterministic noise with garbage in the alpha byte
+    fn random_canvas(len: usize) -> Vec<u32> {
+        let mut state: u32 = 0x12345678;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        }).collect()
+    }
+
+    #[test]
+    fn from_rgb_matches_the_formula() {
+        let black = YCbCr::from_rgb(0xFF000000);
+        let white = YCbCr::from_rgb(0xFFFFFFFF);
+        assert_eq!((black.y, black.cb, black.cr), (16, 128, 128));
+        assert_eq!((white.y, white.cb, white.cr), (235, 128, 128));
+    }
+
+    /// Every colour, plus a few pixels so the scalar tail is covered too
+    fn every_colour() -> Vec<u32> {
+        (0..(1 << 24) + 5).map(|rgb| 0xFF000000 | rgb as u32).collect()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn avx2_matches_scalar_on_every_colour() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let canvas = every_colour();
+        let expected = planes(&canvas, convert_scalar);
+        let actual = planes(&canvas, |canvas, y, cb, cr| unsafe { avx2::convert(canvas, y, cb, cr) });
+        assert_same(&actual, &expected);
+    }
+
+    #[test]
+    fn parallel_matches_scalar_on_every_colour() {
+        let canvas = every_colour();
+        let expected = planes(&canvas, convert_scalar);
+        let actual = planes(&canvas, |canvas, y, cb, cr| convert_parallel(3, canvas, y, cb, cr));
+        assert_same(&actual, &expected);
+    }
+
+    #[test]
+    fn parallel_matches_scalar() {
+        // Odd width and height, so neither the threads nor the vectors split evenly
+        let canvas = random_canvas(853*479);
+        let expected = planes(&canvas, convert_scalar);
+        for threads in [1, 2, 3, 7] {
+            let actual = planes(&canvas, |canvas, y, cb, cr| convert_parallel(threads, canvas, y, cb, cr));
+            assert_same(&actual, &expected);
+        }
+    }
+
+    #[test]
+    fn frame_matches_scalar() {
+        let canvas = random_canvas(1001*601);
+        let expected = planes(&canvas, convert_scalar);
+        let mut frame = Frame::default();
+        frame.from_canvas(&canvas);
+        assert_same(&Planes {y: frame.y_plane, cb: frame.cb_plane, cr: frame.cr_plane}, &expected);
+    }
+}